@@ -1,11 +1,12 @@
 use anyhow::{Context, Result};
 use colored::*;
 use comments_docsource::CommentsDatabase;
+use highlight::Highlighter;
 use lazy_static::lazy_static;
 use manix::*;
 use options_docsource::{OptionsDatabase, OptionsDatabaseType};
 use std::path::PathBuf;
-use structopt::clap::arg_enum;
+use structopt::clap::{arg_enum, AppSettings};
 use structopt::StructOpt;
 
 arg_enum! {
@@ -25,7 +26,16 @@ lazy_static! {
 }
 
 #[derive(StructOpt)]
-#[structopt(name = "manix")]
+enum Command {
+    /// Shows the entries named exactly NAME, fails if there are none
+    Show {
+        #[structopt(name = "NAME")]
+        name: String,
+    },
+}
+
+#[derive(StructOpt)]
+#[structopt(name = "manix", setting = AppSettings::SubcommandsNegateReqs)]
 struct Opt {
     /// Force update cache
     #[structopt(short, long)]
@@ -33,15 +43,90 @@ struct Opt {
     /// Matches entries stricly
     #[structopt(short, long)]
     strict: bool,
-    /// Restrict search to chosen sources
+    /// Matches entries fuzzily, tolerating typos
+    #[structopt(short, long, conflicts_with = "strict")]
+    fuzzy: bool,
+    /// Matches abbreviations of camelCase words and dotted segments, like `cSS` for `concatStringsSep`
+    #[structopt(short, long, conflicts_with_all = &["strict", "fuzzy"])]
+    abbreviation: bool,
+    /// Searches descriptions, comments and examples too
+    #[structopt(long, conflicts_with_all = &["strict", "fuzzy", "abbreviation"])]
+    full_text: bool,
+    /// Treats the query as a case-insensitive regular expression
+    #[structopt(long, conflicts_with_all = &["strict", "fuzzy", "abbreviation", "full-text"])]
+    regex: bool,
+    /// Treats the query as a glob over dotted paths, like `services.*.enable` or `programs.**.package`
+    #[structopt(long, conflicts_with_all = &["strict", "fuzzy", "abbreviation", "full-text", "regex"])]
+    glob: bool,
+    /// Searches functions by type signature, like `[string] -> string`
+    #[structopt(
+        long = "type",
+        conflicts_with_all = &["strict", "fuzzy", "abbreviation", "full-text", "regex", "glob"]
+    )]
+    type_signature: bool,
+    /// Shows the entries named exactly QUERY, like the show subcommand
+    #[structopt(
+        long,
+        requires = "QUERY",
+        conflicts_with_all = &["strict", "fuzzy", "abbreviation", "full-text", "regex", "glob", "type-signature"]
+    )]
+    show: bool,
+    /// Ignores accents, so `e` also matches `é`
+    #[structopt(long)]
+    strip_accents: bool,
+    /// Shows at most this many results
+    #[structopt(long)]
+    limit: Option<usize>,
+    /// Skips this many of the best results, to page through them with --limit
+    #[structopt(long, default_value = "0")]
+    offset: usize,
+    /// Reads the Nixpkgs Documentation from a directory of markdown generated by nixdoc, like
+    /// the `lib-docs` of nixpkgs' manual, instead of building it
+    #[structopt(long, parse(from_os_str))]
+    nixdoc_dir: Option<PathBuf>,
+    /// Restrict search to chosen sources, `@hm` or `@lib` in the query restricts it further
     #[structopt(long, possible_values = &Source::variants(), default_value = &SOURCE_VARIANTS, use_delimiter = true)]
     source: Vec<Source>,
-    #[structopt(name = "QUERY")]
-    query: String,
+    /// Required unless a subcommand is used
+    #[structopt(name = "QUERY", required_unless = "show")]
+    query: Option<String>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+fn print_result(result: &SearchResult, highlighter: &Highlighter) {
+    const LINE: &str = "────────────────────";
+    let mut source = result.sources().join(", ");
+    if !result.matched_fields.is_empty() {
+        source.push_str(&format!(
+            " (matched in {})",
+            result.matched_fields.join(", ")
+        ));
+    }
+    println!(
+        "{}\n{}\n{}",
+        source.white(),
+        LINE.green(),
+        result.entry.pretty_printed(&highlighter.for_result(result))
+    );
 }
 
 fn build_source_and_add<T>(
+    source: T,
+    name: &str,
+    path: &PathBuf,
+    aggregate: Option<&mut AggregateDocSource>,
+) -> Option<()>
+where
+    T: 'static + DocSource + Cache + Sync,
+{
+    build_source_with_and_add(source, T::update, name, path, aggregate)
+}
+
+/// Like `build_source_and_add`, updating the source with `update`
+fn build_source_with_and_add<T>(
     mut source: T,
+    update: impl FnOnce(&mut T) -> Result<bool, Errors>,
     name: &str,
     path: &PathBuf,
     aggregate: Option<&mut AggregateDocSource>,
@@ -50,16 +135,15 @@ where
     T: 'static + DocSource + Cache + Sync,
 {
     eprintln!("Building {} cache...", name);
-    if let Err(e) = source
-        .update()
-        .with_context(|| anyhow::anyhow!("Failed to update {}", name))
+    if let Err(e) =
+        update(&mut source).with_context(|| anyhow::anyhow!("Failed to update {}", name))
     {
         eprintln!("{:?}", e);
         return None;
     }
 
     if let Err(e) = source
-        .save(&path)
+        .save(path)
         .with_context(|| format!("Failed to save {} cache", name))
     {
         eprintln!("{:?}", e);
@@ -131,7 +215,7 @@ fn main() -> Result<()> {
 
     let version = std::env!("CARGO_PKG_VERSION");
     let last_version = std::fs::read(&last_version_path)
-        .map(|c| String::from_utf8(c))
+        .map(String::from_utf8)
         .unwrap_or(Ok(version.to_string()))?;
 
     let should_invalidate_cache = version != last_version;
@@ -139,12 +223,18 @@ fn main() -> Result<()> {
     let mut aggregate_source = AggregateDocSource::default();
 
     let mut comment_db = if !should_invalidate_cache && comment_cache_path.exists() {
-        CommentsDatabase::load(&std::fs::read(&comment_cache_path)?)
-            .map_err(|e| anyhow::anyhow!("Failed to load Nixpkgs comments database: {:?}", e))?
+        match CommentsDatabase::load(&std::fs::read(&comment_cache_path)?) {
+            Ok(comment_db) => comment_db,
+            // a cache in an older format is rebuilt, like after a version change
+            Err(e) => {
+                eprintln!("Failed to load Nixpkgs comments database: {:?}", e);
+                CommentsDatabase::new()
+            }
+        }
     } else {
         CommentsDatabase::new()
     };
-    if comment_db.hash_to_defs.len() == 0 {
+    if comment_db.hash_to_defs.is_empty() {
         eprintln!("Building Nixpkgs comments cache...");
     }
     let cache_invalid = comment_db
@@ -157,7 +247,7 @@ fn main() -> Result<()> {
     }
 
     if should_invalidate_cache || opt.update_cache || cache_invalid {
-        if let None = build_source_and_add(
+        if build_source_and_add(
             OptionsDatabase::new(OptionsDatabaseType::HomeManager),
             "Home Manager Options",
             &options_hm_cache_path,
@@ -166,7 +256,9 @@ fn main() -> Result<()> {
             } else {
                 None
             },
-        ) {
+        )
+        .is_none()
+        {
             eprintln!("Tip: If you installed your home-manager through configuration.nix you can fix this error by adding the home-manager channel with this command: {}", "nix-channel --add https://github.com/rycee/home-manager/archive/master.tar.gz home-manager && nix-channel --update".bold());
         }
 
@@ -192,16 +284,18 @@ fn main() -> Result<()> {
             },
         );
 
-        build_source_and_add(
-            xml_docsource::XmlFuncDocDatabase::new(),
-            "Nixpkgs Documentation",
-            &nixpkgs_doc_cache_path,
-            if opt.source.contains(&Source::nixpkgs_doc) {
-                Some(&mut aggregate_source)
-            } else {
-                None
-            },
-        );
+        if opt.nixdoc_dir.is_none() {
+            build_source_and_add(
+                markdown_docsource::NixpkgsDocDatabase::new(),
+                "Nixpkgs Documentation",
+                &nixpkgs_doc_cache_path,
+                if opt.source.contains(&Source::nixpkgs_doc) {
+                    Some(&mut aggregate_source)
+                } else {
+                    None
+                },
+            );
+        }
 
         std::fs::write(&last_version_path, version)?;
     } else {
@@ -233,10 +327,10 @@ fn main() -> Result<()> {
             );
         }
 
-        if opt.source.contains(&Source::nixpkgs_doc) {
+        if opt.source.contains(&Source::nixpkgs_doc) && opt.nixdoc_dir.is_none() {
             load_source_and_add(
                 std::fs::read(&nixpkgs_doc_cache_path)
-                    .map(|c| xml_docsource::XmlFuncDocDatabase::load(&c)),
+                    .map(|c| markdown_docsource::NixpkgsDocDatabase::load(&c)),
                 "Nixpkgs Documentation",
                 &mut aggregate_source,
                 false,
@@ -244,42 +338,98 @@ fn main() -> Result<()> {
         }
     }
 
-    let query_lower = opt.query.to_ascii_lowercase();
-    let query = manix::Lowercase(query_lower.as_bytes());
-    let entries = if opt.strict {
-        aggregate_source.search(&query)
+    // the directory is read on every run, it's cheap and may have changed since the last one
+    if let Some(dir) = &opt.nixdoc_dir {
+        build_source_with_and_add(
+            markdown_docsource::NixpkgsDocDatabase::new(),
+            |database| database.update_from(dir),
+            "Nixpkgs Documentation",
+            &nixpkgs_doc_cache_path,
+            if opt.source.contains(&Source::nixpkgs_doc) {
+                Some(&mut aggregate_source)
+            } else {
+                None
+            },
+        );
+    }
+
+    let query_text = opt.query.as_deref().unwrap_or_default();
+    let show = match &opt.command {
+        Some(Command::Show { name }) => Some(name.as_str()),
+        None => Some(query_text).filter(|_| opt.show),
+    };
+    if let Some(name) = show {
+        let results = aggregate_source.lookup(name);
+        if results.is_empty() {
+            anyhow::bail!("No entry named {}", name);
+        }
+        let highlighter = Highlighter::default();
+        for result in &results {
+            print_result(result, &highlighter);
+        }
+        return Ok(());
+    }
+
+    let normalization = Normalization {
+        strip_accents: opt.strip_accents,
+    };
+    let query = query::Query::parse_normalized(query_text, normalization);
+    // the skipped results have to be found too, to know which ones come after them
+    let limit = opt.limit.map(|limit| opt.offset + limit);
+    // names are highlighted from what each result matched, text only in full-text searches
+    let highlighter = Highlighter::new(&query, opt.full_text);
+    let results = if opt.strict {
+        aggregate_source.search(&query, limit)
+    } else if opt.fuzzy {
+        aggregate_source.search_fuzzy(&query, limit)
+    } else if opt.abbreviation {
+        aggregate_source.search_abbreviation(&query, limit)
+    } else if opt.full_text {
+        aggregate_source.search_full_text(&query, limit)
+    } else if opt.regex {
+        aggregate_source.search_regex(&compile_regex(query_text)?, limit)
+    } else if opt.glob {
+        aggregate_source.search_glob(&Glob::new(query_text), limit)
+    } else if opt.type_signature {
+        let signature = signature::Signature::parse(query_text)
+            .ok_or_else(|| Errors::Signature(query_text.to_owned()))?;
+        aggregate_source.search_type(&signature, limit)
     } else {
-        aggregate_source.search_liberal(&query)
+        aggregate_source.search_liberal(&query, limit)
     };
-    let (entries, key_only_entries): (Vec<DocEntry>, Vec<DocEntry>) =
-        entries.into_iter().partition(|e| {
-            if let DocEntry::NixpkgsTreeDoc(_) = e {
-                false
-            } else {
-                true
+    if results.is_empty() {
+        println!("{}", "No results found.".bold());
+        // suggestions are based on the names, they don't help with signatures
+        if !opt.type_signature {
+            const SUGGESTIONS: usize = 5;
+            let suggestions = aggregate_source.suggestions(&query, SUGGESTIONS);
+            if !suggestions.is_empty() {
+                println!("Did you mean: {}?", suggestions.join(", ").white());
             }
-        });
+        }
+        return Ok(());
+    }
+
+    let (results, key_only_results): (Vec<SearchResult>, Vec<SearchResult>) = results
+        .into_iter()
+        .skip(opt.offset)
+        .partition(|r| !matches!(r.entry, DocEntry::NixpkgsTreeDoc(_)));
 
-    if !key_only_entries.is_empty() {
+    if !key_only_results.is_empty() {
         const SHOW_MAX_LEN: usize = 50;
         print!("{}", "Here's what I found in nixpkgs:".bold());
-        for entry in key_only_entries.iter().take(SHOW_MAX_LEN) {
-            print!(" {}", entry.name().white());
+        for result in key_only_results.iter().take(SHOW_MAX_LEN) {
+            let highlighter = highlighter.for_result(result);
+            print!(" {}", highlighter.name(&result.entry.name(), |s| s.white()));
         }
-        if key_only_entries.len() > SHOW_MAX_LEN {
-            print!(" and {} more.", key_only_entries.len() - SHOW_MAX_LEN);
+        if key_only_results.len() > SHOW_MAX_LEN {
+            print!(" and {} more.", key_only_results.len() - SHOW_MAX_LEN);
         }
         println!("\n");
     }
 
-    for entry in entries {
-        const LINE: &str = "────────────────────";
-        println!(
-            "{}\n{}\n{}",
-            entry.source().white(),
-            LINE.green(),
-            entry.pretty_printed()
-        );
+    for result in &results {
+        print_result(result, &highlighter);
     }
 
     Ok(())
@@ -1,21 +1,26 @@
 use crate::{
-    contains_insensitive_ascii, starts_with_insensitive_ascii, Cache, DocEntry, DocSource, Errors,
-    Lowercase,
+    abbreviation_score_insensitive_ascii, full_text_match, fuzzy_score_insensitive_ascii,
+    highlight::Highlighter, index::SearchIndex, markdown_docsource::parse_function,
+    match_score_insensitive_ascii, prefix_score_insensitive_ascii, query::Query, regex_score,
+    signature::Signature, top_results, Cache, DocEntry, DocSource, Errors, Filterable, Glob, Match,
+    SearchResult, SCORE_EXACT, SOURCE_NIXPKGS_COMMENTS,
 };
 use colored::*;
-use lazy_static::lazy_static;
 use rayon::prelude::*;
+use regex::bytes::Regex;
 use rnix::{
-    types::{AttrSet, EntryHolder, Ident, KeyValue, Lambda, TypedNode},
+    types::{
+        AttrSet, EntryHolder, Ident, Inherit, KeyValue, Lambda, Select, TokenWrapper, TypedNode,
+        Wrapper,
+    },
     NodeOrToken, SyntaxKind, SyntaxNode, WalkEvent,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::{path::PathBuf, process::Command};
-
-lazy_static! {
-    static ref NIXPKGS_PATH: PathBuf = get_nixpkgs_root();
-}
+use std::{borrow::Cow, collections::BTreeMap};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 fn find_comments(node: SyntaxNode) -> Option<Vec<String>> {
     let mut node = NodeOrToken::Node(node);
@@ -49,18 +54,110 @@ fn find_comments(node: SyntaxNode) -> Option<Vec<String>> {
     Some(comments)
 }
 
+/// Segments of the key of `entry`, like `a`, `b` and `c` for `a.b.c = ...`
+fn key_segments(entry: &KeyValue) -> Option<Vec<String>> {
+    let segments: Vec<String> = entry
+        .key()?
+        .path()
+        .map(|node| match Ident::cast(node.clone()) {
+            Some(ident) => ident.as_str().to_owned(),
+            None => node.text().to_string(),
+        })
+        .collect();
+    (!segments.is_empty()).then_some(segments)
+}
+
+/// Attribute path of the set `node` is in, like `a.b` for `c` in `{ a = { b.c = x: x; }; }`
+fn enclosing_path(node: &SyntaxNode) -> Option<Vec<String>> {
+    let mut segments = Vec::new();
+    let mut node = node.clone();
+    while let Some(set) = node
+        .parent()
+        .filter(|parent| parent.kind() == SyntaxKind::NODE_ATTR_SET)
+    {
+        let outer = match set.parent().and_then(KeyValue::cast) {
+            Some(outer) => outer,
+            None => break,
+        };
+        let mut outer_segments = key_segments(&outer)?;
+        outer_segments.append(&mut segments);
+        segments = outer_segments;
+        node = outer.node().clone();
+    }
+    Some(segments)
+}
+
+/// Attribute path of `entry` in its file, through the sets it's nested in, like `a.b.c` for
+/// `c` in `{ a = { b.c = x: x; }; }`
+fn attr_path(entry: &KeyValue) -> Option<String> {
+    let mut segments = enclosing_path(entry.node())?;
+    segments.append(&mut key_segments(entry)?);
+    Some(segments.join("."))
+}
+
+/// Segments of an expression selecting an attribute, like `self` and `strings` for
+/// `self.strings`. None for any other expression.
+fn select_path(node: &SyntaxNode) -> Option<Vec<String>> {
+    if let Some(ident) = Ident::cast(node.clone()) {
+        return Some(vec![ident.as_str().to_owned()]);
+    }
+    let select = Select::cast(node.clone())?;
+    let mut segments = select_path(&select.set()?)?;
+    segments.push(Ident::cast(select.index()?)?.as_str().to_owned());
+    Some(segments)
+}
+
+/// Aliases for the names of `inherit (source) a b;` or `inherit a b;`
+fn visit_inherit(inherit: Inherit) -> Vec<CommentDocumentation> {
+    let source = match inherit.from() {
+        Some(from) => match from.inner().and_then(|inner| select_path(&inner)) {
+            Some(source) => source,
+            None => return Vec::new(),
+        },
+        None => Vec::new(),
+    };
+    let prefix = match enclosing_path(inherit.node()) {
+        Some(prefix) => prefix,
+        None => return Vec::new(),
+    };
+
+    inherit
+        .idents()
+        .map(|ident| {
+            let name = ident.as_str();
+            let attr_path = prefix.iter().map(String::as_str).chain(Some(name));
+            let target = source.iter().map(String::as_str).chain(Some(name));
+            CommentDocumentation::alias(
+                attr_path.collect::<Vec<_>>().join("."),
+                target.collect::<Vec<_>>().join("."),
+            )
+        })
+        .collect()
+}
+
 fn visit_attr_entry(entry: KeyValue) -> Option<CommentDocumentation> {
-    let ident = Ident::cast(entry.key()?.path().nth(0)?)?.node().text();
+    let attr_path = attr_path(&entry)?;
+    let ident = attr_path.rsplit('.').next()?;
     let lambda = Lambda::cast(entry.value()?)?;
 
     let comments = find_comments(lambda.node().clone()).unwrap_or_default();
+    // the closest doc comment is the documentation, other comments are notes for maintainers
+    let doc_comment = comments.iter().rev().find_map(|c| doc_comment_content(c));
 
-    Some(CommentDocumentation::new(ident.to_string(), comments))
+    Some(
+        match doc_comment {
+            Some(doc_comment) => CommentDocumentation::new(ident.to_string(), Vec::new())
+                .with_doc_comment(doc_comment),
+            None => CommentDocumentation::new(ident.to_string(), comments),
+        }
+        .with_attr_path(attr_path),
+    )
 }
 
 fn visit_attrset(set: &AttrSet) -> Vec<CommentDocumentation> {
     set.entries()
         .flat_map(|e| visit_attr_entry(e).into_iter())
+        .chain(set.inherits().flat_map(visit_inherit))
         .collect()
 }
 
@@ -80,19 +177,98 @@ fn walk_ast(ast: rnix::AST) -> Vec<CommentDocumentation> {
     res
 }
 
+/// Definitions in a file of nixpkgs. Only aliases in `lib` are kept, as only those are resolved;
+/// the others, like each package's `inherit pname version;`, would never be shown.
+fn definitions_in(path: &Path, content: &str) -> Vec<CommentDocumentation> {
+    walk_ast(rnix::parse(content))
+        .into_iter()
+        .map(|def| def.with_path(path.to_path_buf()))
+        .filter(|def| def.alias_of.is_none() || def.lib_file_segments().is_some())
+        .collect()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct CommentDocumentation {
+    /// Name of the definition, the last segment of `attr_path`
     pub key: String,
+    /// Attribute path of the definition in its file, like `types.str` or `concatStrings`
+    pub attr_path: String,
+    /// Path of the defining file, relative to nixpkgs
     pub path: Option<PathBuf>,
     pub comments: Vec<String>,
+    /// Markdown of an RFC 145 `/** ... */` doc comment, used instead of `comments` if set
+    pub doc_comment: Option<String>,
+    /// The comment without the sections below
+    pub description: String,
+    /// From a `Type:` line, or a `# Type` section
+    pub fn_type: Option<String>,
+    /// From a `# Inputs` section
+    pub args: Vec<(String, String)>,
+    /// From an `Example:` block, or a `# Examples` section
+    pub example: Option<String>,
+    /// For `inherit`ed names, the inherited attribute in the scope of the file, like
+    /// `self.strings.concatStrings`
+    pub alias_of: Option<String>,
+    /// Position in `CommentsDatabase::hash_to_defs` of the definition `alias_of` resolved to
+    pub original_at: Option<(u32, u32)>,
+    /// That definition, whose documentation is shown for the alias. It isn't cached, it's
+    /// linked from `original_at` when the database is loaded
+    #[serde(skip)]
+    pub original: Option<Box<CommentDocumentation>>,
 }
 
 impl CommentDocumentation {
     pub fn new(key: String, comments: Vec<String>) -> Self {
+        let text = comments
+            .iter()
+            .map(|c| cleanup_comment(c))
+            .collect::<Vec<&str>>()
+            .join("\n");
+        let (description, fn_type, example) = legacy_sections(&strip_indentation(&text));
         Self {
+            attr_path: key.clone(),
             key,
             comments,
             path: None,
+            doc_comment: None,
+            description,
+            fn_type,
+            args: Vec::new(),
+            example,
+            alias_of: None,
+            original_at: None,
+            original: None,
+        }
+    }
+    /// An `inherit`ed name at `attr_path`, for the attribute `target` in the scope of the file
+    pub fn alias(attr_path: String, target: String) -> Self {
+        CommentDocumentation {
+            alias_of: Some(target),
+            ..Self::new(String::new(), Vec::new())
+        }
+        .with_attr_path(attr_path)
+    }
+    pub fn with_doc_comment(self, doc_comment: String) -> Self {
+        let lines: Vec<&str> = doc_comment.lines().collect();
+        let parsed = parse_function(&self.key, &lines);
+        CommentDocumentation {
+            description: parsed.description,
+            fn_type: parsed.fn_type,
+            args: parsed.args,
+            example: parsed.example,
+            doc_comment: Some(doc_comment),
+            ..self
+        }
+    }
+    pub fn with_attr_path(self, attr_path: String) -> Self {
+        CommentDocumentation {
+            key: attr_path
+                .rsplit('.')
+                .next()
+                .unwrap_or(&attr_path)
+                .to_owned(),
+            attr_path,
+            ..self
         }
     }
     pub fn with_path(self, path: PathBuf) -> Self {
@@ -103,97 +279,311 @@ impl CommentDocumentation {
     }
 }
 
+impl Filterable for CommentDocumentation {
+    fn filter_field(&self, field: &str) -> Option<Cow<'_, str>> {
+        match field {
+            "path" => self.path.as_ref().map(|path| path.to_string_lossy()),
+            "type" => self.documentation().fn_type.as_deref().map(Cow::from),
+            "source" => Some(Cow::from(SOURCE_NIXPKGS_COMMENTS)),
+            _ => None,
+        }
+    }
+}
+
 pub fn cleanup_comment(s: &str) -> &str {
     s.trim_start_matches("#")
         .trim_start_matches("/*")
         .trim_end_matches("*/")
 }
 
+/// Markdown of an RFC 145 doc comment like `/** Concatenates strings */`, with the indentation
+/// common to its lines stripped. None if it's any other kind of comment.
+fn doc_comment_content(comment: &str) -> Option<String> {
+    let content = comment.strip_prefix("/**")?.strip_suffix("*/")?;
+    Some(strip_indentation(content))
+}
+
+/// Strips the indentation common to the lines of a comment, and the blank lines around it
+fn strip_indentation(content: &str) -> String {
+    let mut lines = content.lines();
+    // text right after `/**` isn't indented like the lines below it
+    let first = lines.next().unwrap_or_default().trim();
+    let rest: Vec<&str> = lines.collect();
+    let indentation = rest
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    let lines: Vec<&str> = std::iter::once(first)
+        .chain(
+            rest.iter()
+                .map(|l| l.get(indentation..).unwrap_or("").trim_end()),
+        )
+        .collect();
+    lines.join("\n").trim_matches('\n').to_owned()
+}
+
+/// Splits a comment into its description, and the `Type:` line and `Example:` block lib's
+/// comments had before RFC 145, like
+///
+/// ```text
+/// Concatenate a list of strings.
+///
+/// Type: concatStrings :: [string] -> string
+///
+/// Example:
+///   concatStrings ["foo" "bar"]
+///   => "foobar"
+/// ```
+fn legacy_sections(text: &str) -> (String, Option<String>, Option<String>) {
+    let mut description = Vec::new();
+    let mut fn_type = None;
+    let mut example: Option<Vec<&str>> = None;
+    let mut in_example = false;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if let Some(signature) = trimmed.strip_prefix("Type:") {
+            fn_type = Some(signature.trim().to_owned()).filter(|s| !s.is_empty());
+            in_example = false;
+        } else if trimmed == "Example:" || trimmed == "Examples:" {
+            example.get_or_insert_with(Vec::new);
+            in_example = true;
+        } else if in_example && (trimmed.is_empty() || line.starts_with(char::is_whitespace)) {
+            example.as_mut().unwrap().push(line);
+        } else {
+            in_example = false;
+            description.push(line);
+        }
+    }
+
+    let example = example
+        // after a blank first line, so that all of the lines are dedented
+        .map(|lines| strip_indentation(&format!("\n{}", lines.join("\n"))))
+        .filter(|example| !example.is_empty());
+    let description = strip_indentation(&description.join("\n"));
+    (description, fn_type, example)
+}
+
 impl CommentDocumentation {
-    pub fn pretty_printed(&self) -> String {
-        let heading = self.key.blue().bold();
+    /// Segments of the path of the defining file if it's in `lib`, like `strings` for
+    /// `lib/strings.nix`
+    fn lib_file_segments(&self) -> Option<Vec<String>> {
+        let path = self.path.as_ref()?;
+        let mut segments = Vec::new();
+        for segment in path.with_extension("").strip_prefix("lib").ok()? {
+            segments.push(segment.to_str()?.to_owned());
+        }
+        if segments.last().map(String::as_str) == Some("default") {
+            segments.pop();
+        }
+        Some(segments)
+    }
+    /// Attribute path of functions defined in `lib`, like `lib.strings.concatStrings`
+    /// for `concatStrings` in `lib/strings.nix`
+    pub fn qualified_name(&self) -> Option<String> {
+        let mut segments = vec!["lib".to_owned()];
+        segments.extend(self.lib_file_segments()?);
+        segments.push(self.attr_path.clone());
+        Some(segments.join("."))
+    }
+    /// Best guesses of the public attribute paths of the definition: its `qualified_name`,
+    /// and `lib.concatStrings` for files right in `lib`, as `lib/default.nix` re-exports
+    /// most of their functions
+    pub fn public_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.qualified_name().into_iter().collect();
+        if self
+            .lib_file_segments()
+            .is_some_and(|segments| segments.len() == 1)
+        {
+            paths.push(format!("lib.{}", self.attr_path));
+        }
+        paths
+    }
+    /// Every name the definition can be found by, its public paths and its path in the file
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self.public_paths();
+        names.push(self.attr_path.clone());
+        names
+    }
+    /// The definition whose documentation is shown, the original one for resolved aliases
+    pub fn documentation(&self) -> &CommentDocumentation {
+        self.original.as_deref().unwrap_or(self)
+    }
+    pub fn pretty_printed(&self, highlighter: &Highlighter) -> String {
+        let heading = highlighter.name(&self.name(), |s| s.blue().bold());
         let path = self
             .path
             .as_ref()
-            .map(|path| {
-                path.strip_prefix(NIXPKGS_PATH.to_owned())
-                    .unwrap_or(path)
-                    .display()
-                    .to_string()
-            })
+            .map(|path| path.display().to_string())
             .unwrap_or_default()
             .white();
 
-        let comment = self
-            .comments
-            .iter()
-            .map(|c: &String| cleanup_comment(c))
-            .collect::<Vec<&str>>()
-            .join("\n");
-
-        format!("# {} ({})\n{}\n\n", heading, path, comment)
+        let mut output = format!("# {} ({})\n", heading, path);
+        if let Some(original) = &self.original {
+            output.push_str(&format!(
+                "{} {}\n",
+                "Alias of".white(),
+                original.name().blue()
+            ));
+        }
+        let doc = self.documentation();
+        if let Some(fn_type) = &doc.fn_type {
+            output.push_str(&format!("{}\n", fn_type.cyan()));
+        }
+        output.push_str(&format!(
+            "{}\n",
+            highlighter.text(&doc.description, |s| s.normal())
+        ));
+        if !doc.args.is_empty() {
+            output.push_str("\nArguments:\n");
+            for (name, description) in &doc.args {
+                output.push_str(&format!(
+                    "  {}: {}\n",
+                    highlighter.text(name, |s| s.green()),
+                    highlighter.text(description, |s| s.normal())
+                ));
+            }
+        }
+        if let Some(example) = &doc.example {
+            output.push_str("\nExample:\n");
+            for line in example.lines() {
+                output.push_str(&format!("  {}\n", highlighter.text(line, |s| s.white())));
+            }
+        }
+        output.push('\n');
+        output
     }
     pub fn name(&self) -> String {
-        self.key.to_owned()
+        self.qualified_name()
+            .unwrap_or_else(|| self.attr_path.clone())
+    }
+    /// Whether the definition has a comment, either kind, or is an alias of one that does
+    pub fn is_documented(&self) -> bool {
+        let doc = self.documentation();
+        !doc.comments.is_empty() || doc.doc_comment.is_some()
+    }
+    /// Fields searched in full-text mode, besides the name
+    pub fn text_fields(&self) -> Vec<(&'static str, &str)> {
+        let doc = self.documentation();
+        let mut fields = vec![("comments", doc.description.as_str())];
+        for (name, description) in &doc.args {
+            fields.push(("args", name));
+            fields.push(("args", description));
+        }
+        if let Some(example) = &doc.example {
+            fields.push(("example", example));
+        }
+        fields
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CommentsDatabase {
-    pub hash_to_defs: HashMap<u32, Vec<CommentDocumentation>>,
+    pub hash_to_defs: BTreeMap<u32, Vec<CommentDocumentation>>,
+    /// Documented definitions, as file hash and position in `hash_to_defs`
+    index: SearchIndex<(u32, u32)>,
 }
 
 impl DocSource for CommentsDatabase {
+    fn sources(&self) -> Vec<&str> {
+        vec![SOURCE_NIXPKGS_COMMENTS]
+    }
     fn all_keys(&self) -> Vec<&str> {
         self.hash_to_defs
             .values()
             .flatten()
-            .map(|def| def.key.as_ref())
+            .filter(|def| def.is_documented())
+            .map(|def| def.attr_path.as_ref())
             .collect()
     }
-    fn search(&self, query: &Lowercase) -> Vec<DocEntry> {
-        self.hash_to_defs
-            .values()
-            .flatten()
-            .filter(|d| {
-                d.comments.len() > 0 && starts_with_insensitive_ascii(d.key.as_bytes(), query)
-            })
-            .cloned()
-            .map(DocEntry::CommentDoc)
-            .collect()
+    fn search(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, self.index.prefix_candidates(query), |key, d| {
+            query.score(key, d, prefix_score_insensitive_ascii)
+        })
     }
-    fn search_liberal(&self, query: &Lowercase) -> Vec<DocEntry> {
-        self.hash_to_defs
-            .values()
-            .flatten()
-            .filter(|d| d.comments.len() > 0 && contains_insensitive_ascii(d.key.as_bytes(), query))
-            .cloned()
-            .map(DocEntry::CommentDoc)
-            .collect()
+    fn search_liberal(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, self.index.key_candidates(query), |key, d| {
+            query.score(key, d, match_score_insensitive_ascii)
+        })
+    }
+    fn search_fuzzy(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |key, d| {
+            query.score(key, d, fuzzy_score_insensitive_ascii)
+        })
+    }
+    fn search_abbreviation(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |key, d| {
+            query.score(key, d, abbreviation_score_insensitive_ascii)
+        })
+    }
+    fn search_regex(&self, regex: &Regex, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |key, _| regex_score(key, regex))
+    }
+    fn search_glob(&self, glob: &Glob, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(
+            limit,
+            self.index.literal_candidates(glob.longest_literal()),
+            |key, _| glob.score_dotted(key),
+        )
+    }
+    fn lookup(&self, name: &str) -> Vec<SearchResult> {
+        let candidates = self.index.exact_candidates(name);
+        self.search_with(None, Some(candidates), |key, _| {
+            (key == name.as_bytes()).then(|| Match::range(SCORE_EXACT, 0..name.len()))
+        })
+    }
+    fn search_type(&self, signature: &Signature, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |_, d| {
+            let fn_type = Signature::parse(d.documentation().fn_type.as_ref()?)?;
+            Some(Match::new(signature.score(&fn_type)?, Vec::new()))
+        })
+    }
+    fn search_full_text(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        let matches = self
+            .candidates(self.index.full_text_candidates(query))
+            .into_iter()
+            .filter_map(|d| {
+                let text_fields = d.text_fields();
+                let shown = d.name();
+                let (m, fields) = d
+                    .names()
+                    .iter()
+                    .filter_map(|name| {
+                        let (m, fields) = full_text_match(name.as_bytes(), d, &text_fields, query)?;
+                        Some((m.aligned(name, &shown), fields))
+                    })
+                    .max_by_key(|(m, _)| m.score)?;
+                Some((m, (d, fields)))
+            })
+            .collect();
+        top_results(matches, limit, |m, (d, fields)| {
+            let entry = DocEntry::CommentDoc(d.clone());
+            SearchResult::new(entry, m.score)
+                .with_matched_fields(fields)
+                .with_name_ranges(m.ranges)
+        })
     }
     fn update(&mut self) -> Result<bool, Errors> {
-        let files = find_nix_files(get_nixpkgs_root())
+        let root = get_nixpkgs_root();
+        let files = find_nix_files(root.clone())
             .par_iter()
             .map(|f| {
                 let content = std::fs::read_to_string(f.path()).unwrap();
                 let mut hasher = crc32fast::Hasher::new();
                 hasher.update(content.as_bytes());
                 let hash = hasher.finalize();
-                (hash, f.path().to_path_buf(), content)
+                // relative, so the cached definitions of a file still apply when nixpkgs moves
+                let path = f.path().strip_prefix(&root).unwrap_or(f.path());
+                (hash, path.to_path_buf(), content)
             })
             .collect::<Vec<(u32, PathBuf, String)>>();
 
         let new_defs = files
             .par_iter()
             .filter(|(hash, _, _)| !self.is_in_cache(hash))
-            .map(|(hash, path, content)| {
-                let ast = rnix::parse(&content);
-                let definitions = walk_ast(ast)
-                    .into_iter()
-                    .map(|def| def.with_path(path.clone()))
-                    .collect();
-                (hash, definitions)
-            })
+            .map(|(hash, path, content)| (hash, definitions_in(path, content)))
             .collect::<Vec<(&u32, Vec<CommentDocumentation>)>>();
         if new_defs.is_empty() {
             return Ok(false);
@@ -202,17 +592,162 @@ impl DocSource for CommentsDatabase {
         for (hash, defs) in new_defs {
             self.add_to_cache(*hash, defs);
         }
+        self.resolve_aliases();
+        self.build_index();
 
         Ok(true)
     }
 }
-impl Cache for CommentsDatabase {}
+impl Cache for CommentsDatabase {
+    fn load<'a>(content: &'a [u8]) -> Result<Self, Errors>
+    where
+        Self: Deserialize<'a>,
+    {
+        let mut database: Self = bincode::deserialize(content)?;
+        database.link_aliases();
+        Ok(database)
+    }
+}
+
+impl Default for CommentsDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl CommentsDatabase {
     pub fn new() -> Self {
         Self {
-            hash_to_defs: HashMap::new(),
+            hash_to_defs: BTreeMap::new(),
+            index: SearchIndex::default(),
+        }
+    }
+
+    /// Points the aliases in `lib` to the definitions they inherit. `inherit (self.strings) x`
+    /// and `inherit (lib) x` refer to the public paths of definitions, plain `inherit x` and
+    /// other sources to definitions in the same file.
+    fn resolve_aliases(&mut self) {
+        let mut public = BTreeMap::new();
+        let mut local = BTreeMap::new();
+        for (hash, defs) in &self.hash_to_defs {
+            for (i, def) in defs.iter().enumerate() {
+                if def.alias_of.is_some() {
+                    continue;
+                }
+                let position = (*hash, i as u32);
+                for path in def.public_paths() {
+                    public.entry(path).or_insert(position);
+                }
+                local.insert((*hash, def.attr_path.as_str()), position);
+            }
         }
+
+        let mut originals = Vec::new();
+        for (hash, defs) in &self.hash_to_defs {
+            for (i, def) in defs.iter().enumerate() {
+                let target = match &def.alias_of {
+                    Some(target) if def.lib_file_segments().is_some() => target,
+                    _ => continue,
+                };
+                let original = match target.split_once('.') {
+                    Some(("self", rest)) | Some(("lib", rest)) => {
+                        public.get(&format!("lib.{}", rest)).copied()
+                    }
+                    _ => local.get(&(*hash, target.as_str())).copied(),
+                };
+                originals.push((*hash, i, original));
+            }
+        }
+
+        for (hash, i, original) in originals {
+            if let Some(defs) = self.hash_to_defs.get_mut(&hash) {
+                defs[i].original_at = original;
+            }
+        }
+        self.link_aliases();
+    }
+
+    /// Sets the `original` of the aliases from their `original_at`
+    fn link_aliases(&mut self) {
+        let hash_to_defs = &self.hash_to_defs;
+        let originals: Vec<_> = hash_to_defs
+            .iter()
+            .flat_map(|(hash, defs)| {
+                defs.iter()
+                    .enumerate()
+                    .filter(|(_, def)| def.alias_of.is_some())
+                    .map(move |(i, def)| {
+                        let original = def.original_at.and_then(|(hash, j)| {
+                            hash_to_defs.get(&hash)?.get(j as usize).cloned()
+                        });
+                        (*hash, i, original.map(Box::new))
+                    })
+            })
+            .collect();
+        for (hash, i, original) in originals {
+            if let Some(defs) = self.hash_to_defs.get_mut(&hash) {
+                defs[i].original = original;
+            }
+        }
+    }
+
+    fn build_index(&mut self) {
+        let defs: Vec<_> = self
+            .hash_to_defs
+            .iter()
+            .flat_map(|(hash, defs)| {
+                defs.iter()
+                    .enumerate()
+                    .filter(|(_, d)| d.is_documented())
+                    .map(move |(i, d)| ((*hash, i as u32), d, d.names()))
+            })
+            .collect();
+        self.index = SearchIndex::with_aliases(defs.iter().map(|(position, d, names)| {
+            let names = names.iter().map(String::as_str).collect();
+            (*position, names, d.text_fields())
+        }));
+    }
+
+    /// Documented definitions at the given positions, or all of them if the index couldn't
+    /// narrow them down
+    fn candidates(&self, positions: Option<Vec<&(u32, u32)>>) -> Vec<&CommentDocumentation> {
+        match positions {
+            Some(positions) => positions
+                .into_iter()
+                .map(|(hash, i)| &self.hash_to_defs[hash][*i as usize])
+                .collect(),
+            None => self
+                .hash_to_defs
+                .values()
+                .flatten()
+                .filter(|d| d.is_documented())
+                .collect(),
+        }
+    }
+
+    fn search_with(
+        &self,
+        limit: Option<usize>,
+        positions: Option<Vec<&(u32, u32)>>,
+        score: impl Fn(&[u8], &CommentDocumentation) -> Option<Match>,
+    ) -> Vec<SearchResult> {
+        let matches = self
+            .candidates(positions)
+            .into_iter()
+            .filter_map(|d| {
+                // matched against every name, the ranges are moved to the one that's shown
+                let shown = d.name();
+                let m = d
+                    .names()
+                    .iter()
+                    .filter_map(|n| Some(score(n.as_bytes(), d)?.aligned(n, &shown)))
+                    .max_by_key(|m| m.score)?;
+                Some((m, d))
+            })
+            .collect();
+        top_results(matches, limit, |m, d| {
+            SearchResult::new(DocEntry::CommentDoc(d.clone()), m.score).with_name_ranges(m.ranges)
+        })
     }
 
     fn is_in_cache(&self, hash: &u32) -> bool {
@@ -252,3 +787,316 @@ fn get_nixpkgs_root() -> PathBuf {
         PathBuf::from(".")
     }
 }
+
+#[test]
+fn test_doc_comment_content() {
+    assert_eq!(
+        doc_comment_content("/** Concatenate a list of strings. */"),
+        Some("Concatenate a list of strings.".to_owned())
+    );
+    assert_eq!(
+        doc_comment_content(
+            "/**\n    Concatenate a list of strings.\n\n    # Example\n\n    ```nix\n      concatStrings [ \"a\" ]\n    ```\n  */"
+        ),
+        Some(
+            "Concatenate a list of strings.\n\n# Example\n\n```nix\n  concatStrings [ \"a\" ]\n```"
+                .to_owned()
+        )
+    );
+    assert_eq!(doc_comment_content("/* Not a doc comment */"), None);
+    assert_eq!(doc_comment_content("/**/"), None);
+    assert_eq!(doc_comment_content("# Not either"), None);
+}
+
+#[test]
+fn test_walk_ast() {
+    let ast = rnix::parse(
+        r#"{
+          # Not documented with a doc comment
+          plain = x: x;
+
+          # TODO: make it faster
+          /**
+            Concatenate a list of strings.
+
+            # Inputs
+          */
+          concatStrings = list: list;
+
+          # A dotted definition
+          strings.escape = list: list;
+
+          types = {
+            # A nested definition
+            either = a: b: a;
+          };
+        }"#,
+    );
+    let definitions = walk_ast(ast);
+    assert_eq!(
+        definitions,
+        vec![
+            CommentDocumentation::new(
+                "plain".to_owned(),
+                vec!["# Not documented with a doc comment".to_owned()]
+            ),
+            CommentDocumentation::new("concatStrings".to_owned(), Vec::new())
+                .with_doc_comment("Concatenate a list of strings.\n\n# Inputs".to_owned()),
+            CommentDocumentation::new(
+                "escape".to_owned(),
+                vec!["# A dotted definition".to_owned()]
+            )
+            .with_attr_path("strings.escape".to_owned()),
+            CommentDocumentation::new(
+                "either".to_owned(),
+                vec!["# A nested definition".to_owned()]
+            )
+            .with_attr_path("types.either".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn test_sections() {
+    let legacy = CommentDocumentation::new(
+        "concatStrings".to_owned(),
+        vec![r#"/* Concatenate a list of strings.
+
+       Type: concatStrings :: [string] -> string
+
+       Example:
+         concatStrings ["foo" "bar"]
+         => "foobar"
+    */"#
+        .to_owned()],
+    );
+    assert_eq!(legacy.description, "Concatenate a list of strings.");
+    assert_eq!(
+        legacy.fn_type.as_deref(),
+        Some("concatStrings :: [string] -> string")
+    );
+    assert_eq!(
+        legacy.example.as_deref(),
+        Some("concatStrings [\"foo\" \"bar\"]\n=> \"foobar\"")
+    );
+
+    let hashes = CommentDocumentation::new(
+        "id".to_owned(),
+        vec![
+            "# The identity function".to_owned(),
+            "# For when a function is required as first class value.".to_owned(),
+            "#".to_owned(),
+            "# Type: id :: a -> a".to_owned(),
+        ],
+    );
+    assert_eq!(
+        hashes.description,
+        "The identity function\nFor when a function is required as first class value."
+    );
+    assert_eq!(hashes.fn_type.as_deref(), Some("id :: a -> a"));
+    assert_eq!(hashes.example, None);
+
+    let doc_comment = CommentDocumentation::new("concatStringsSep".to_owned(), Vec::new())
+        .with_doc_comment(
+            r#"Concatenate a list of strings with a separator.
+
+# Inputs
+
+`sep`
+: Separator to add between elements
+
+`list`
+: List of input strings
+
+# Type
+
+```
+concatStringsSep :: string -> [string] -> string
+```
+
+# Examples
+:::{.example}
+## `lib.strings.concatStringsSep` usage example
+
+```nix
+concatStringsSep "/" ["usr" "local" "bin"]
+=> "usr/local/bin"
+```
+
+:::"#
+                .to_owned(),
+        );
+    assert_eq!(
+        doc_comment.description,
+        "Concatenate a list of strings with a separator."
+    );
+    assert_eq!(
+        doc_comment.fn_type.as_deref(),
+        Some("concatStringsSep :: string -> [string] -> string")
+    );
+    assert_eq!(
+        doc_comment.args,
+        vec![
+            (
+                "sep".to_owned(),
+                "Separator to add between elements".to_owned()
+            ),
+            ("list".to_owned(), "List of input strings".to_owned()),
+        ]
+    );
+    assert_eq!(
+        doc_comment.example.as_deref(),
+        Some("concatStringsSep \"/\" [\"usr\" \"local\" \"bin\"]\n=> \"usr/local/bin\"")
+    );
+}
+
+#[test]
+fn test_aliases() {
+    let walk = |path: &str, source: &str| definitions_in(Path::new(path), source);
+    let strings = walk(
+        "lib/strings.nix",
+        r#"{ lib }: rec {
+          inherit (builtins) stringLength;
+
+          # Concatenate a list of strings.
+          concatStrings = list: list;
+
+          deprecated = { inherit concatStrings; };
+        }"#,
+    );
+    let default = walk(
+        "lib/default.nix",
+        r#"self: let
+          # A function only used in lib/default.nix
+          internal = x: x;
+        in {
+          inherit (self.strings) concatStrings stringLength;
+          inherit internal;
+          strings = { inherit (self.lists) concatStrings; };
+        }"#,
+    );
+    assert_eq!(
+        default
+            .iter()
+            .map(|d| (d.attr_path.as_str(), d.alias_of.as_deref()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("concatStrings", Some("self.strings.concatStrings")),
+            ("stringLength", Some("self.strings.stringLength")),
+            ("internal", Some("internal")),
+            ("strings.concatStrings", Some("self.lists.concatStrings")),
+        ]
+    );
+
+    // aliases outside of lib are never resolved, they aren't kept
+    let package = walk(
+        "pkgs/tools/hello/default.nix",
+        r#"{ stdenv }: stdenv.mkDerivation rec {
+          pname = "hello";
+          src = { inherit pname; };
+        }"#,
+    );
+    assert!(package.is_empty());
+
+    let mut database = CommentsDatabase::new();
+    database.add_to_cache(1, strings);
+    database.add_to_cache(2, default);
+    database.resolve_aliases();
+    database.build_index();
+    // the cache only has the originals' positions, they're linked again when it's loaded
+    let cached = bincode::serialize(&database).unwrap();
+    let uncached: CommentsDatabase = bincode::deserialize(&cached).unwrap();
+    assert!(uncached
+        .hash_to_defs
+        .values()
+        .flatten()
+        .all(|d| d.original.is_none()));
+    assert!(uncached.hash_to_defs[&2][0].original_at.is_some());
+    let database = CommentsDatabase::load(&cached).unwrap();
+    assert_eq!(
+        database.all_keys(),
+        vec!["concatStrings", "deprecated.concatStrings", "concatStrings"]
+    );
+
+    let found = |name: &str| -> Vec<(String, Option<String>)> {
+        database
+            .lookup(name)
+            .into_iter()
+            .map(|result| match result.entry {
+                DocEntry::CommentDoc(d) => {
+                    let original = d.original.as_ref().map(|o| o.name());
+                    (d.name(), original)
+                }
+                _ => unreachable!(),
+            })
+            .collect()
+    };
+    assert_eq!(
+        found("lib.concatStrings"),
+        vec![
+            ("lib.strings.concatStrings".to_owned(), None),
+            (
+                "lib.concatStrings".to_owned(),
+                Some("lib.strings.concatStrings".to_owned())
+            ),
+        ]
+    );
+    assert_eq!(found("lib.strings.concatStrings").len(), 1);
+    assert_eq!(
+        found("lib.strings.deprecated.concatStrings"),
+        vec![(
+            "lib.strings.deprecated.concatStrings".to_owned(),
+            Some("lib.strings.concatStrings".to_owned())
+        )]
+    );
+    // the builtin and let binding aren't documented, neither are the aliases of them
+    assert!(found("lib.stringLength").is_empty());
+    assert!(found("internal").is_empty());
+}
+
+#[test]
+fn test_lookup() {
+    let definitions = walk_ast(rnix::parse(
+        r#"{
+          # Concatenate a list of strings.
+          concatStrings = list: list;
+
+          types = {
+            # Strings.
+            str = x: x;
+          };
+
+          undocumented = x: x;
+        }"#,
+    ));
+    let mut database = CommentsDatabase::new();
+    database.add_to_cache(
+        1,
+        definitions
+            .into_iter()
+            .map(|d| d.with_path("lib/strings.nix".into()))
+            .collect(),
+    );
+    database.build_index();
+    let lookup = |name: &str| -> Vec<String> {
+        database
+            .lookup(name)
+            .into_iter()
+            .map(|result| result.entry.name())
+            .collect()
+    };
+
+    assert_eq!(lookup("concatStrings"), vec!["lib.strings.concatStrings"]);
+    assert_eq!(
+        lookup("lib.strings.concatStrings"),
+        vec!["lib.strings.concatStrings"]
+    );
+    assert_eq!(
+        lookup("lib.concatStrings"),
+        vec!["lib.strings.concatStrings"]
+    );
+    assert_eq!(lookup("types.str"), vec!["lib.strings.types.str"]);
+    assert!(lookup("str").is_empty());
+    assert!(lookup("concat").is_empty());
+    assert!(lookup("undocumented").is_empty());
+}
@@ -0,0 +1,123 @@
+use crate::{
+    match_ranges_insensitive_ascii, merge_ranges, query::Query, Lowercase, Normalization,
+    SearchResult,
+};
+use colored::*;
+use std::ops::Range;
+
+/// What a search matched, to highlight it in the names and text of the results
+#[derive(Debug, Clone, Default)]
+pub struct Highlighter {
+    /// Case folded terms of the query
+    terms: Vec<Vec<u8>>,
+    normalization: Normalization,
+    /// Whether text other than names is highlighted too, for full-text searches
+    text: bool,
+    /// Ranges of the name of the result being printed that the search matched
+    name_ranges: Vec<Range<usize>>,
+}
+
+impl Highlighter {
+    /// Highlights the terms of `query` in text, except negated ones and filters.
+    /// Descriptions, comments and examples are only highlighted if `text` is set.
+    pub fn new(query: &Query, text: bool) -> Self {
+        let terms = query
+            .alternatives()
+            .iter()
+            .flatten()
+            .filter(|term| term.field.is_none() && !term.negated)
+            .map(|term| term.text.clone())
+            .collect();
+        Self {
+            terms,
+            normalization: query.normalization(),
+            text,
+            name_ranges: Vec::new(),
+        }
+    }
+
+    /// The highlighter for printing `result`, highlighting the ranges of its name that matched
+    pub fn for_result(&self, result: &SearchResult) -> Self {
+        Self {
+            name_ranges: result.name_ranges.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// Sorted, non-overlapping ranges of the terms of the query in `s`
+    pub fn ranges(&self, s: &str) -> Vec<Range<usize>> {
+        let (folded, offsets) = self.normalization.fold_with_offsets(s);
+        let ranges = self
+            .terms
+            .iter()
+            .flat_map(|term| match_ranges_insensitive_ascii(&folded, &Lowercase(term)))
+            .map(|range| offsets[range.start].start..offsets[range.end - 1].end)
+            .collect();
+        merge_ranges(ranges)
+    }
+
+    /// Renders the name of the result with `style`, and the ranges that matched highlighted
+    pub fn name(&self, s: &str, style: impl Fn(&str) -> ColoredString) -> String {
+        // ranges of another name than the result's, if any, are left out
+        let ranges: Vec<Range<usize>> = self
+            .name_ranges
+            .iter()
+            .filter(|range| s.get(range.start..range.end).is_some())
+            .cloned()
+            .collect();
+        highlighted(s, &ranges, style)
+    }
+
+    /// Renders text like a description with `style`, and its matches highlighted if this is
+    /// a full-text search
+    pub fn text(&self, s: &str, style: impl Fn(&str) -> ColoredString) -> String {
+        if self.text {
+            highlighted(s, &self.ranges(s), style)
+        } else {
+            style(s).to_string()
+        }
+    }
+}
+
+/// Styles the parts of `s` in between `ranges` with `style`, and the ranges themselves with
+/// `style` and a highlight on top
+fn highlighted(s: &str, ranges: &[Range<usize>], style: impl Fn(&str) -> ColoredString) -> String {
+    let mut output = String::with_capacity(s.len());
+    let mut position = 0;
+    for range in ranges {
+        if position < range.start {
+            output.push_str(&style(&s[position..range.start]).to_string());
+        }
+        output.push_str(&style(&s[range.clone()]).yellow().underline().to_string());
+        position = range.end;
+    }
+    if position < s.len() {
+        output.push_str(&style(&s[position..]).to_string());
+    }
+    output
+}
+
+#[test]
+fn test_ranges() {
+    let ranges = |query: &str, s: &str| Highlighter::new(&Query::parse(query), false).ranges(s);
+    assert_eq!(ranges("nginx", "services.nginx.enable"), vec![9..14]);
+    assert_eq!(
+        ranges("NGINX ena -services type:bool", "services.nginx.enable"),
+        vec![9..14, 15..18]
+    );
+    assert_eq!(ranges("nginx OR ngin x", "nginx"), vec![0..5]);
+    assert_eq!(ranges("école", "programs.École.enable"), vec![9..15]);
+    assert_eq!(
+        ranges("caddy", "services.nginx.enable"),
+        Vec::<Range<usize>>::new()
+    );
+
+    let strip_accents = Normalization {
+        strip_accents: true,
+    };
+    let query = Query::parse_normalized("ecole", strip_accents);
+    assert_eq!(
+        Highlighter::new(&query, false).ranges("programs.École.enable"),
+        vec![9..15]
+    );
+}
@@ -0,0 +1,528 @@
+use crate::{query::Query, Normalization};
+use fst::{IntoStreamer, Streamer};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, iter::FromIterator};
+
+/// Inverted index from lowercased trigrams to the ids of the documents containing them.
+///
+/// It only narrows down the candidates for a substring query, every candidate still has to be
+/// checked against the real text.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrigramIndex {
+    postings: HashMap<[u8; 3], Postings>,
+}
+
+/// Sorted document ids, delta and varint encoded to keep the cache files small
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Postings {
+    last: Option<u32>,
+    bytes: Vec<u8>,
+}
+
+impl Postings {
+    fn push(&mut self, id: u32) {
+        let mut delta = match self.last {
+            Some(last) if last == id => return,
+            Some(last) => id - last,
+            None => id,
+        };
+        self.last = Some(id);
+
+        loop {
+            let byte = (delta & 0x7f) as u8;
+            delta >>= 7;
+            if delta == 0 {
+                self.bytes.push(byte);
+                break;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    fn ids(&self) -> Vec<u32> {
+        let mut ids = Vec::new();
+        let mut current = 0u32;
+        let mut delta = 0u32;
+        let mut shift = 0;
+        for byte in &self.bytes {
+            delta |= ((byte & 0x7f) as u32) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                current += delta;
+                ids.push(current);
+                delta = 0;
+                shift = 0;
+            }
+        }
+        ids
+    }
+}
+
+/// Text is indexed with accents stripped, so the index can be used by queries
+/// normalized either way
+const INDEX_NORMALIZATION: Normalization = Normalization {
+    strip_accents: true,
+};
+
+fn fold(text: &[u8]) -> Vec<u8> {
+    INDEX_NORMALIZATION.fold(text).to_ascii_lowercase()
+}
+
+fn trigrams(text: &[u8]) -> Vec<[u8; 3]> {
+    fold(text).windows(3).map(|w| [w[0], w[1], w[2]]).collect()
+}
+
+impl TrigramIndex {
+    /// Adds `text` to the index under `id`. Ids have to be inserted in increasing order
+    pub fn insert(&mut self, id: u32, text: &str) {
+        for trigram in trigrams(text.as_bytes()) {
+            self.postings.entry(trigram).or_default().push(id);
+        }
+    }
+
+    /// Sorted ids of the documents which may contain `query`.
+    /// Returns None when the query is too short to be looked up.
+    pub fn candidates(&self, query: &[u8]) -> Option<Vec<u32>> {
+        let mut result: Option<Vec<u32>> = None;
+        for trigram in trigrams(query) {
+            let ids = self
+                .postings
+                .get(&trigram)
+                .map(Postings::ids)
+                .unwrap_or_default();
+            result = Some(match result {
+                Some(result) => intersect(&result, &ids),
+                None => ids,
+            });
+        }
+        result
+    }
+}
+
+/// Lowercased keys in a finite state transducer, mapping to the ids of the documents having them.
+///
+/// The keys are sorted, so those starting with a prefix are next to each other and found with
+/// a single range lookup, and the prefixes they share are only stored once.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrefixIndex {
+    /// Serialized `fst::Map` from every distinct key to the position of its first id in `ids`
+    fst: Vec<u8>,
+    /// Document ids, ordered by their key
+    ids: Vec<u32>,
+}
+
+impl PrefixIndex {
+    fn new(keys: impl Iterator<Item = (u32, String)>) -> Self {
+        let mut keys: Vec<_> = keys.map(|(id, key)| (fold(key.as_bytes()), id)).collect();
+        keys.sort_unstable();
+
+        let mut builder = fst::MapBuilder::memory();
+        for (i, (key, _)) in keys.iter().enumerate() {
+            if i == 0 || keys[i - 1].0 != *key {
+                // keys are inserted sorted and only once, so this can't fail
+                builder.insert(key, i as u64).unwrap();
+            }
+        }
+        Self {
+            fst: builder.into_inner().unwrap(),
+            ids: keys.into_iter().map(|(_, id)| id).collect(),
+        }
+    }
+
+    /// Sorted ids of the documents whose key starts with `prefix`
+    pub fn candidates(&self, prefix: &[u8]) -> Vec<u32> {
+        let prefix = fold(prefix);
+        self.ids_between(&prefix, successor(&prefix).as_deref(), |key| {
+            key.starts_with(&prefix)
+        })
+    }
+
+    /// Sorted ids of the documents whose key is `key`, ignoring case
+    pub fn exact(&self, key: &[u8]) -> Vec<u32> {
+        let key = fold(key);
+        let mut after = key.clone();
+        after.push(0);
+        self.ids_between(&key, Some(&after), |k| k == key.as_slice())
+    }
+
+    /// Ids of the documents from the first key at least `from`, if it `matches`, up to the
+    /// first key at least `to`
+    fn ids_between(
+        &self,
+        from: &[u8],
+        to: Option<&[u8]>,
+        matches: impl Fn(&[u8]) -> bool,
+    ) -> Vec<u32> {
+        let map = match fst::Map::new(self.fst.as_slice()) {
+            Ok(map) => map,
+            // not built yet
+            Err(_) => return Vec::new(),
+        };
+        let first_at_least = |key: &[u8]| {
+            let mut stream = map.range().ge(key).into_stream();
+            stream
+                .next()
+                .map(|(key, position)| (matches(key), position as usize))
+        };
+
+        let start = match first_at_least(from) {
+            Some((true, start)) => start,
+            _ => return Vec::new(),
+        };
+        let end = to
+            .and_then(first_at_least)
+            .map_or(self.ids.len(), |(_, end)| end);
+
+        let mut ids = self.ids[start..end].to_vec();
+        ids.sort_unstable();
+        // documents with several keys can have more than one of them in the range
+        ids.dedup();
+        ids
+    }
+}
+
+/// The smallest string greater than all the strings starting with `prefix`, if any
+fn successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut successor = prefix.to_vec();
+    while let Some(last) = successor.pop() {
+        if last < u8::MAX {
+            successor.push(last + 1);
+            return Some(successor);
+        }
+    }
+    None
+}
+
+/// Serializes sorted and deduplicated strings as a `fst::Set`, which is a lot smaller than
+/// the strings themselves when they share prefixes. Use with `#[serde(with = "...")]`
+pub mod fst_strings {
+    use fst::Streamer;
+    use serde::{de::Error as _, ser::Error as _, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(strings: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+        let set = fst::Set::from_iter(strings).map_err(S::Error::custom)?;
+        serializer.serialize_bytes(set.as_fst().as_bytes())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<String>, D::Error> {
+        let set = fst::Set::new(Vec::<u8>::deserialize(deserializer)?).map_err(D::Error::custom)?;
+        let mut strings = Vec::with_capacity(set.len());
+        let mut stream = set.stream();
+        while let Some(string) = stream.next() {
+            strings.push(String::from_utf8(string.to_vec()).map_err(D::Error::custom)?);
+        }
+        Ok(strings)
+    }
+}
+
+fn intersect(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+    let mut result = Vec::new();
+    while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+        match x.cmp(y) {
+            std::cmp::Ordering::Less => {
+                a.next();
+            }
+            std::cmp::Ordering::Greater => {
+                b.next();
+            }
+            std::cmp::Ordering::Equal => {
+                result.push(**x);
+                a.next();
+                b.next();
+            }
+        }
+    }
+    result
+}
+
+fn union(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = [a, b].concat();
+    result.sort_unstable();
+    result.dedup();
+    result
+}
+
+/// Index over the keys and the text fields of a documentation source.
+///
+/// `K` is whatever the source needs to find an entry again, the position of the entry in
+/// `docs` is its id in the trigram indexes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchIndex<K> {
+    docs: Vec<K>,
+    keys: TrigramIndex,
+    text: TrigramIndex,
+    prefixes: PrefixIndex,
+}
+
+impl<K> Default for SearchIndex<K> {
+    fn default() -> Self {
+        Self {
+            docs: Vec::new(),
+            keys: TrigramIndex::default(),
+            text: TrigramIndex::default(),
+            prefixes: PrefixIndex::default(),
+        }
+    }
+}
+
+/// Builds the index from documents, with their key and text fields
+impl<'a, K> FromIterator<(K, &'a str, Vec<(&'static str, &'a str)>)> for SearchIndex<K> {
+    fn from_iter<I>(docs: I) -> Self
+    where
+        I: IntoIterator<Item = (K, &'a str, Vec<(&'static str, &'a str)>)>,
+    {
+        Self::with_aliases(
+            docs.into_iter()
+                .map(|(doc, key, text_fields)| (doc, vec![key], text_fields)),
+        )
+    }
+}
+
+impl<K> SearchIndex<K> {
+    /// Builds the index from documents known by several keys, like a function and the
+    /// attribute paths it's exported as. A document matches if any of its keys does.
+    pub fn with_aliases<'a>(
+        docs: impl IntoIterator<Item = (K, Vec<&'a str>, Vec<(&'static str, &'a str)>)>,
+    ) -> Self {
+        let mut index = Self::default();
+        let mut keys = Vec::new();
+        for (doc, doc_keys, text_fields) in docs {
+            let id = index.docs.len() as u32;
+            index.docs.push(doc);
+            for key in doc_keys {
+                index.keys.insert(id, key);
+                keys.push((id, key.to_owned()));
+            }
+            for (_, text) in text_fields {
+                index.text.insert(id, text);
+            }
+        }
+        index.prefixes = PrefixIndex::new(keys.into_iter());
+        index
+    }
+
+    fn docs(&self, ids: Option<Vec<u32>>) -> Option<Vec<&K>> {
+        ids.map(|ids| ids.into_iter().map(|id| &self.docs[id as usize]).collect())
+    }
+
+    /// Ids of the documents that may match the query, looking at the text too if `full_text`
+    fn query_ids(&self, query: &Query, full_text: bool) -> Option<Vec<u32>> {
+        let mut result = Vec::new();
+        for terms in query.alternatives() {
+            let mut alternative: Option<Vec<u32>> = None;
+            // filters are checked against other fields, they can't narrow anything down
+            for term in terms.iter().filter(|t| !t.negated && t.field.is_none()) {
+                let ids = match self.keys.candidates(&term.text) {
+                    // too short to narrow anything down
+                    None => continue,
+                    Some(keys) if full_text => {
+                        let text = self.text.candidates(&term.text).unwrap_or_default();
+                        union(&keys, &text)
+                    }
+                    Some(keys) => keys,
+                };
+                alternative = Some(match alternative {
+                    Some(alternative) => intersect(&alternative, &ids),
+                    None => ids,
+                });
+            }
+            result = union(&result, &alternative?);
+        }
+        Some(result)
+    }
+
+    /// Documents whose key may contain `literal`, None if all of them may
+    pub fn literal_candidates(&self, literal: &[u8]) -> Option<Vec<&K>> {
+        self.docs(self.keys.candidates(literal))
+    }
+
+    /// Documents whose key starts with every term of one of the alternatives of `query`,
+    /// None if all of them may
+    pub fn prefix_candidates(&self, query: &Query) -> Option<Vec<&K>> {
+        let mut result = Vec::new();
+        for terms in query.alternatives() {
+            let mut alternative: Option<Vec<u32>> = None;
+            for term in terms.iter().filter(|t| !t.negated && t.field.is_none()) {
+                let ids = self.prefixes.candidates(&term.text);
+                alternative = Some(match alternative {
+                    Some(alternative) => intersect(&alternative, &ids),
+                    None => ids,
+                });
+            }
+            result = union(&result, &alternative?);
+        }
+        self.docs(Some(result))
+    }
+
+    /// Documents whose key is `key`, ignoring case
+    pub fn exact_candidates(&self, key: &str) -> Vec<&K> {
+        self.prefixes
+            .exact(key.as_bytes())
+            .into_iter()
+            .map(|id| &self.docs[id as usize])
+            .collect()
+    }
+
+    /// Documents whose key may match `query`, None if all of them may
+    pub fn key_candidates(&self, query: &Query) -> Option<Vec<&K>> {
+        self.docs(self.query_ids(query, false))
+    }
+
+    /// Documents that may match `query` with their key or text, None if all of them may
+    pub fn full_text_candidates(&self, query: &Query) -> Option<Vec<&K>> {
+        self.docs(self.query_ids(query, true))
+    }
+}
+
+#[test]
+fn test_postings() {
+    let mut postings = Postings::default();
+    for id in &[0, 1, 1, 5, 300, 70000] {
+        postings.push(*id);
+    }
+    assert_eq!(postings.ids(), vec![0, 1, 5, 300, 70000]);
+}
+
+#[test]
+fn test_search_index() {
+    let index: SearchIndex<_> = vec![
+        (
+            "mkIf",
+            "lib.mkIf",
+            vec![("description", "Conditional definition")],
+        ),
+        (
+            "mkMerge",
+            "lib.mkMerge",
+            vec![("description", "Merges definitions")],
+        ),
+        ("concat", "lib.concatStrings", vec![]),
+    ]
+    .into_iter()
+    .collect();
+
+    let key_candidates = |q: &str| index.key_candidates(&Query::parse(q));
+    assert_eq!(key_candidates("mkif"), Some(vec![&"mkIf"]));
+    assert_eq!(key_candidates("lib.mk"), Some(vec![&"mkIf", &"mkMerge"]));
+    assert_eq!(key_candidates("mk"), None);
+    assert_eq!(key_candidates("xyz"), Some(vec![]));
+    assert_eq!(
+        key_candidates("mkif OR concat"),
+        Some(vec![&"mkIf", &"concat"])
+    );
+    assert_eq!(key_candidates("mkif OR mk"), None);
+    assert_eq!(
+        key_candidates("lib -mkif"),
+        Some(vec![&"mkIf", &"mkMerge", &"concat"])
+    );
+    assert_eq!(
+        index.literal_candidates(b".mk"),
+        Some(vec![&"mkIf", &"mkMerge"])
+    );
+
+    let full_text_candidates = |q: &str| index.full_text_candidates(&Query::parse(q));
+    assert_eq!(
+        full_text_candidates("lib definition"),
+        Some(vec![&"mkIf", &"mkMerge"])
+    );
+    assert_eq!(
+        full_text_candidates("merge definition"),
+        Some(vec![&"mkMerge"])
+    );
+    assert_eq!(full_text_candidates("a b"), None);
+
+    let index: SearchIndex<_> = vec![
+        ("résumé", "lib.résumé", vec![]),
+        ("resume", "lib.resume", vec![]),
+    ]
+    .into_iter()
+    .collect();
+    let key_candidates = |q: &str| index.key_candidates(&Query::parse(q));
+    assert_eq!(key_candidates("RÉSUMÉ"), Some(vec![&"résumé", &"resume"]));
+    assert_eq!(key_candidates("resume"), Some(vec![&"résumé", &"resume"]));
+}
+
+#[test]
+fn test_prefix_candidates() {
+    let index: SearchIndex<_> = vec![
+        ("mkIf", "lib.mkIf", vec![]),
+        ("mkif", "lib.mkif", vec![]),
+        ("mkMerge", "lib.mkMerge", vec![]),
+        ("concat", "lib.concatStrings", vec![]),
+        ("nginx", "services.nginx.enable", vec![]),
+    ]
+    .into_iter()
+    .collect();
+
+    let prefix_candidates = |q: &str| index.prefix_candidates(&Query::parse(q));
+    assert_eq!(prefix_candidates(""), None);
+    assert_eq!(prefix_candidates("LIB.MKIF"), Some(vec![&"mkIf", &"mkif"]));
+    assert_eq!(
+        prefix_candidates("lib.m"),
+        Some(vec![&"mkIf", &"mkif", &"mkMerge"])
+    );
+    assert_eq!(
+        prefix_candidates("l"),
+        Some(vec![&"mkIf", &"mkif", &"mkMerge", &"concat"])
+    );
+    assert_eq!(prefix_candidates("mk"), Some(vec![]));
+    assert_eq!(
+        prefix_candidates("lib.c OR services"),
+        Some(vec![&"concat", &"nginx"])
+    );
+    assert_eq!(prefix_candidates("lib.mkm -foo"), Some(vec![&"mkMerge"]));
+    assert_eq!(index.exact_candidates("lib.MKIF"), vec![&"mkIf", &"mkif"]);
+    assert_eq!(index.exact_candidates("lib.mkMerg"), Vec::<&&str>::new());
+    assert_eq!(index.exact_candidates("lib"), Vec::<&&str>::new());
+    assert_eq!(
+        SearchIndex::<u32>::default().prefix_candidates(&Query::parse("lib")),
+        Some(vec![])
+    );
+}
+
+#[test]
+fn test_with_aliases() {
+    let index = SearchIndex::with_aliases(vec![
+        (
+            "concatStrings",
+            vec!["lib.strings.concatStrings", "lib.concatStrings"],
+            vec![],
+        ),
+        ("mkIf", vec!["lib.mkIf", "lib.modules.mkIf"], vec![]),
+    ]);
+
+    let prefix_candidates = |q: &str| index.prefix_candidates(&Query::parse(q));
+    assert_eq!(
+        prefix_candidates("lib.strings."),
+        Some(vec![&"concatStrings"])
+    );
+    assert_eq!(
+        prefix_candidates("lib."),
+        Some(vec![&"concatStrings", &"mkIf"])
+    );
+    assert_eq!(
+        prefix_candidates("lib.concat OR lib.strings.concat"),
+        Some(vec![&"concatStrings"])
+    );
+    assert_eq!(
+        index.key_candidates(&Query::parse("modules")),
+        Some(vec![&"mkIf"])
+    );
+    assert_eq!(index.exact_candidates("lib.mkif"), vec![&"mkIf"]);
+}
+
+#[test]
+fn test_fst_strings() {
+    #[derive(Serialize, Deserialize)]
+    struct Strings(#[serde(with = "fst_strings")] Vec<String>);
+
+    let strings = vec!["lib".to_owned(), "lib.mkIf".to_owned(), "pkgs".to_owned()];
+    let bytes = bincode::serialize(&Strings(strings.clone())).unwrap();
+    assert_eq!(bincode::deserialize::<Strings>(&bytes).unwrap().0, strings);
+
+    let unsorted = vec!["pkgs".to_owned(), "lib".to_owned()];
+    assert!(bincode::serialize(&Strings(unsorted)).is_err());
+}
@@ -1,13 +1,30 @@
 use comments_docsource::CommentDocumentation;
+use highlight::Highlighter;
 use options_docsource::{OptionDocumentation, OptionsDatabaseType};
+use query::Query;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use std::path::PathBuf;
+use regex::bytes::{Regex, RegexBuilder};
+use signature::Signature;
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
 use xml_docsource::XmlFuncDocumentation;
 
 pub mod comments_docsource;
+pub mod highlight;
+pub mod index;
+pub mod markdown_docsource;
 pub mod nixpkgs_tree_docsource;
 pub mod options_docsource;
+pub mod query;
+pub mod signature;
+pub mod suggestions;
 pub mod xml_docsource;
 
 pub trait Cache
@@ -47,8 +64,21 @@ pub enum Errors {
         filename: String,
         err: roxmltree::Error,
     },
+    #[error("Invalid regular expression: {}", .0)]
+    Regex(#[from] regex::Error),
+    #[error("Invalid type signature: {}", .0)]
+    Signature(String),
+    #[error("Failed to build the Nixpkgs function documentation: {}", .0)]
+    DocBuild(String),
 }
 
+/// Names of the sources, as returned by `DocEntry::source`
+pub const SOURCE_NIXOS_OPTIONS: &str = "NixOS Options";
+pub const SOURCE_HOME_MANAGER_OPTIONS: &str = "HomeManager Options";
+pub const SOURCE_NIXPKGS_COMMENTS: &str = "Nixpkgs Comments";
+pub const SOURCE_NIXPKGS_DOCUMENTATION: &str = "Nixpkgs Documentation";
+pub const SOURCE_NIXPKGS_TREE: &str = "Nixpkgs Tree";
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum DocEntry {
     OptionDoc(OptionsDatabaseType, OptionDocumentation),
@@ -66,31 +96,234 @@ impl DocEntry {
             DocEntry::NixpkgsTreeDoc(x) => x.clone(),
         }
     }
-    pub fn pretty_printed(&self) -> String {
+    pub fn pretty_printed(&self, highlighter: &Highlighter) -> String {
         match self {
-            DocEntry::OptionDoc(_, x) => x.pretty_printed(),
-            DocEntry::CommentDoc(x) => x.pretty_printed(),
-            DocEntry::XmlFuncDoc(x) => x.pretty_printed(),
+            DocEntry::OptionDoc(_, x) => x.pretty_printed(highlighter),
+            DocEntry::CommentDoc(x) => x.pretty_printed(highlighter),
+            DocEntry::XmlFuncDoc(x) => x.pretty_printed(highlighter),
             DocEntry::NixpkgsTreeDoc(x) => x.clone(),
         }
     }
     pub fn source(&self) -> &str {
         match self {
-            DocEntry::OptionDoc(typ, _) => match typ {
-                OptionsDatabaseType::NixOS => "NixOS Options",
-                OptionsDatabaseType::HomeManager => "HomeManager Options",
-            },
-            DocEntry::CommentDoc(_) => "Nixpkgs Comments",
-            DocEntry::XmlFuncDoc(_) => "Nixpkgs Documentation",
-            DocEntry::NixpkgsTreeDoc(_) => "Nixpkgs Tree",
+            DocEntry::OptionDoc(typ, _) => typ.source(),
+            DocEntry::CommentDoc(_) => SOURCE_NIXPKGS_COMMENTS,
+            DocEntry::XmlFuncDoc(_) => SOURCE_NIXPKGS_DOCUMENTATION,
+            DocEntry::NixpkgsTreeDoc(_) => SOURCE_NIXPKGS_TREE,
+        }
+    }
+    /// Bonus added to the score of this entry's matches, used to break ties between sources
+    pub fn source_weight(&self) -> u32 {
+        match self {
+            DocEntry::XmlFuncDoc(_) => 40,
+            DocEntry::CommentDoc(_) => 30,
+            DocEntry::OptionDoc(OptionsDatabaseType::NixOS, _) => 20,
+            DocEntry::OptionDoc(OptionsDatabaseType::HomeManager, _) => 10,
+            DocEntry::NixpkgsTreeDoc(_) => 0,
+        }
+    }
+    /// Public attribute paths of the entry, and the file defining it if known.
+    ///
+    /// Entries sharing one of the paths, with no conflicting files, describe the same thing.
+    /// None if the entry can't be a duplicate.
+    pub fn identity(&self) -> Option<(Vec<String>, Option<PathBuf>)> {
+        match self {
+            DocEntry::OptionDoc(_, _) => None,
+            DocEntry::CommentDoc(x) => {
+                Some((x.public_paths(), x.path.clone())).filter(|(paths, _)| !paths.is_empty())
+            }
+            DocEntry::XmlFuncDoc(x) => Some((vec![x.name()], x.defining_file())),
+            DocEntry::NixpkgsTreeDoc(x) => Some((vec![x.clone()], None)),
+        }
+    }
+}
+
+/// An entry that matched a query, with its relevance score. Higher scores are better
+#[derive(Debug, PartialEq, Eq)]
+pub struct SearchResult {
+    pub entry: DocEntry,
+    pub score: u32,
+    /// Text fields, other than the name, that the query was found in
+    pub matched_fields: Vec<&'static str>,
+    /// Ranges of the entry's name that the query matched, see `Match`
+    pub name_ranges: Vec<Range<usize>>,
+    /// Other entries describing the same thing, see `DocEntry::identity`
+    pub duplicates: Vec<DocEntry>,
+}
+
+impl SearchResult {
+    /// `match_score` is how well the key matched, the entry's source weight is added to it
+    pub fn new(entry: DocEntry, match_score: u32) -> Self {
+        let score = match_score + entry.source_weight();
+        Self {
+            entry,
+            score,
+            matched_fields: Vec::new(),
+            name_ranges: Vec::new(),
+            duplicates: Vec::new(),
+        }
+    }
+    /// A result for an entry looked up by its name, the whole of it matched
+    pub(crate) fn exact(entry: DocEntry) -> Self {
+        let name = 0..entry.name().len();
+        Self::new(entry, SCORE_EXACT).with_name_ranges(vec![name])
+    }
+    pub fn with_matched_fields(self, matched_fields: Vec<&'static str>) -> Self {
+        SearchResult {
+            matched_fields,
+            ..self
+        }
+    }
+    pub fn with_name_ranges(self, name_ranges: Vec<Range<usize>>) -> Self {
+        SearchResult {
+            name_ranges,
+            ..self
+        }
+    }
+    /// Sources of the entry and of its duplicates
+    pub fn sources(&self) -> Vec<&str> {
+        let mut sources = Vec::new();
+        for source in std::iter::once(&self.entry)
+            .chain(&self.duplicates)
+            .map(DocEntry::source)
+        {
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+        sources
+    }
+
+    /// Whether `entry`, sharing a public path with this result, can be merged into it.
+    /// It has to be defined in the same file if that's known, and its source can't have
+    /// another entry of the same name in the result.
+    fn is_duplicate(&self, entry: &DocEntry, file: Option<&Path>) -> bool {
+        std::iter::once(&self.entry)
+            .chain(&self.duplicates)
+            .all(|other| {
+                let other_file = other.identity().and_then(|(_, file)| file);
+                (other.source() != entry.source() || other.name() != entry.name())
+                    && (file.is_none() || other_file.is_none() || other_file.as_deref() == file)
+            })
+    }
+
+    /// Key results are shown in: best score first, then most detailed source, then by name
+    fn ordering_key(&self) -> (Reverse<u32>, Reverse<u32>, String) {
+        (
+            Reverse(self.score),
+            Reverse(self.entry.source_weight()),
+            self.entry.name(),
+        )
+    }
+
+    fn merge(&mut self, mut other: SearchResult) {
+        // the most detailed entry is shown, the tree only has names
+        if other.entry.source_weight() > self.entry.source_weight() {
+            std::mem::swap(&mut self.entry, &mut other.entry);
+            std::mem::swap(&mut self.matched_fields, &mut other.matched_fields);
+            std::mem::swap(&mut self.name_ranges, &mut other.name_ranges);
+        }
+        self.score = self.score.max(other.score);
+        self.duplicates.push(other.entry);
+        self.duplicates.append(&mut other.duplicates);
+    }
+}
+
+/// Turns the `limit` best scoring matches into results, in no particular order.
+///
+/// Broad queries can match most entries of a source, this only clones the ones that are kept.
+/// Of matches with the same score, the first ones are kept.
+pub(crate) fn top_results<T>(
+    matches: Vec<(Match, T)>,
+    limit: Option<usize>,
+    into_result: impl Fn(Match, T) -> SearchResult,
+) -> Vec<SearchResult> {
+    let mut matches: Vec<(usize, Match, T)> = matches
+        .into_iter()
+        .enumerate()
+        .map(|(i, (m, value))| (i, m, value))
+        .collect();
+    if let Some(limit) = limit.filter(|limit| *limit < matches.len()) {
+        if limit > 0 {
+            matches.select_nth_unstable_by_key(limit - 1, |(i, m, _)| (Reverse(m.score), *i));
         }
+        matches.truncate(limit);
     }
+    matches
+        .into_iter()
+        .map(|(_, m, value)| into_result(m, value))
+        .collect()
+}
+
+/// Merges results describing the same thing into the best scoring of them.
+/// `results` have to be sorted, best first.
+pub fn merge_duplicates(results: Vec<SearchResult>) -> Vec<SearchResult> {
+    let mut merged: Vec<SearchResult> = Vec::with_capacity(results.len());
+    let mut by_name: HashMap<String, Vec<usize>> = HashMap::new();
+    for result in results {
+        let (names, file) = match result.entry.identity() {
+            Some(identity) => identity,
+            None => {
+                merged.push(result);
+                continue;
+            }
+        };
+        // the best scoring result it can be merged into, through any of its names
+        let duplicate = names
+            .iter()
+            .filter_map(|name| by_name.get(name))
+            .flatten()
+            .copied()
+            .filter(|i| merged[*i].is_duplicate(&result.entry, file.as_deref()))
+            .min();
+        let position = match duplicate {
+            Some(i) => {
+                merged[i].merge(result);
+                i
+            }
+            None => {
+                merged.push(result);
+                merged.len() - 1
+            }
+        };
+        for name in names {
+            let positions = by_name.entry(name).or_default();
+            if !positions.contains(&position) {
+                positions.push(position);
+            }
+        }
+    }
+    merged
+}
+
+/// Fields of an entry that `field:value` query filters are checked against, see `Query`
+pub trait Filterable {
+    /// Value of the field, None if the entry doesn't have it
+    fn filter_field(&self, field: &str) -> Option<Cow<'_, str>>;
 }
 
 pub trait DocSource {
+    /// Sources of its entries, as named by `DocEntry::source`
+    fn sources(&self) -> Vec<&str>;
     fn all_keys(&self) -> Vec<&str>;
-    fn search(&self, query: &Lowercase) -> Vec<DocEntry>;
-    fn search_liberal(&self, query: &Lowercase) -> Vec<DocEntry>;
+    fn search(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult>;
+    fn search_liberal(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult>;
+    /// Typo-tolerant search
+    fn search_fuzzy(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult>;
+    /// Matches abbreviations made of camelCase humps and dotted segments, like `css` for
+    /// `concatStringsSep`, see `abbreviation_ranges_insensitive_ascii`
+    fn search_abbreviation(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult>;
+    /// Searches descriptions, comments and examples too, every term of the query has to be
+    /// found in the name or one of these
+    fn search_full_text(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult>;
+    /// Matches the keys against a regular expression, see `compile_regex`
+    fn search_regex(&self, regex: &Regex, limit: Option<usize>) -> Vec<SearchResult>;
+    /// Matches the dotted attribute paths against a glob, see `Glob`
+    fn search_glob(&self, glob: &Glob, limit: Option<usize>) -> Vec<SearchResult>;
+    /// Entries named exactly `name`
+    fn lookup(&self, name: &str) -> Vec<SearchResult>;
+    /// Matches the type signatures of functions, see `Signature::score`
+    fn search_type(&self, signature: &Signature, limit: Option<usize>) -> Vec<SearchResult>;
 
     /// Updates the cache, returns true if anything changed
     fn update(&mut self) -> Result<bool, Errors>;
@@ -105,34 +338,158 @@ impl AggregateDocSource {
     pub fn add_source(&mut self, source: Box<dyn DocSource + Sync>) {
         self.sources.push(source)
     }
+
+    /// Up to `count` keys of all the sources that are close to `query`, see
+    /// `suggestions::suggestions`
+    pub fn suggestions(&self, query: &Query, count: usize) -> Vec<String> {
+        suggestions::suggestions(&self.all_keys(), query, count)
+            .into_iter()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// Like `search_sorted`, only searching the sources picked in `query`
+    fn search_query<F>(&self, query: &Query, limit: Option<usize>, search: F) -> Vec<SearchResult>
+    where
+        F: Fn(&(dyn DocSource + Sync)) -> Vec<SearchResult> + Sync,
+    {
+        self.search_sorted(limit, |source| {
+            if source.sources().iter().any(|s| query.includes_source(s)) {
+                search(source)
+            } else {
+                Vec::new()
+            }
+        })
+    }
+
+    /// Runs `search` on every source, and keeps the `limit` best results.
+    ///
+    /// Every source returns up to `limit` results too, so there can be less than `limit`
+    /// left once duplicates are merged. The order is the same from one run to the next, see
+    /// `SearchResult::ordering_key`.
+    fn search_sorted<F>(&self, limit: Option<usize>, search: F) -> Vec<SearchResult>
+    where
+        F: Fn(&(dyn DocSource + Sync)) -> Vec<SearchResult> + Sync,
+    {
+        let mut results: Vec<SearchResult> = self
+            .sources
+            .par_iter()
+            .flat_map(|source| search(source.as_ref()))
+            .collect();
+        results.sort_by_cached_key(SearchResult::ordering_key);
+        let mut results = merge_duplicates(results);
+        // merging can show an entry of another source, with another name
+        results.sort_by_cached_key(SearchResult::ordering_key);
+        if let Some(limit) = limit {
+            results.truncate(limit);
+        }
+        results
+    }
 }
 
 impl DocSource for AggregateDocSource {
-    fn all_keys(&self) -> Vec<&str> {
+    fn sources(&self) -> Vec<&str> {
         self.sources
-            .par_iter()
-            .flat_map(|source| source.all_keys())
+            .iter()
+            .flat_map(|source| source.sources())
             .collect()
     }
-    fn search(&self, query: &Lowercase) -> Vec<DocEntry> {
+    fn all_keys(&self) -> Vec<&str> {
         self.sources
             .par_iter()
-            .flat_map(|source| source.search(query))
+            .flat_map(|source| source.all_keys())
             .collect()
     }
-    fn search_liberal(&self, query: &Lowercase) -> Vec<DocEntry> {
-        self.sources
-            .par_iter()
-            .flat_map(|source| source.search_liberal(query))
-            .collect()
+    fn search(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_query(query, limit, |source| source.search(query, limit))
+    }
+    fn search_liberal(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_query(query, limit, |source| source.search_liberal(query, limit))
+    }
+    fn search_fuzzy(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_query(query, limit, |source| source.search_fuzzy(query, limit))
+    }
+    fn search_abbreviation(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_query(query, limit, |source| {
+            source.search_abbreviation(query, limit)
+        })
+    }
+    fn search_full_text(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_query(query, limit, |source| source.search_full_text(query, limit))
+    }
+    fn search_regex(&self, regex: &Regex, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_sorted(limit, |source| source.search_regex(regex, limit))
+    }
+    fn search_glob(&self, glob: &Glob, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_sorted(limit, |source| source.search_glob(glob, limit))
+    }
+    fn lookup(&self, name: &str) -> Vec<SearchResult> {
+        self.search_sorted(None, |source| source.lookup(name))
+    }
+    fn search_type(&self, signature: &Signature, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_sorted(limit, |source| source.search_type(signature, limit))
     }
     fn update(&mut self) -> Result<bool, Errors> {
         unimplemented!();
     }
 }
 
+/// A lowercase string to match case-insensitively against
 pub struct Lowercase<'a>(pub &'a [u8]);
 
+/// How queries, and the text they're matched against, are normalized before comparing them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Normalization {
+    /// Strips accents and other combining marks, so `e` matches `é`
+    pub strip_accents: bool,
+}
+
+impl Normalization {
+    /// Applies Unicode case folding to `s`, and strips accents if enabled.
+    ///
+    /// ASCII is returned untouched, as the matching helpers compare it case-insensitively
+    /// byte by byte, which is a lot faster.
+    pub fn fold<'a>(&self, s: &'a [u8]) -> Cow<'a, [u8]> {
+        if s.is_ascii() {
+            return Cow::Borrowed(s);
+        }
+
+        let folded = caseless::default_case_fold_str(&String::from_utf8_lossy(s));
+        let normalized: String = if self.strip_accents {
+            folded.nfd().filter(|c| !is_combining_mark(*c)).collect()
+        } else {
+            folded.nfc().collect()
+        };
+        Cow::Owned(normalized.into_bytes())
+    }
+
+    /// Folds `s` like `fold`, one character at a time, along with the range of `s` each
+    /// folded byte comes from. Used to map matches in the folded text back to `s`.
+    pub fn fold_with_offsets(&self, s: &str) -> (Vec<u8>, Vec<Range<usize>>) {
+        if s.is_ascii() {
+            return (
+                s.as_bytes().to_vec(),
+                (0..s.len()).map(|i| i..i + 1).collect(),
+            );
+        }
+
+        let mut folded = Vec::with_capacity(s.len());
+        let mut offsets = Vec::with_capacity(s.len());
+        for (i, c) in s.char_indices() {
+            let original = i..i + c.len_utf8();
+            let c = self.fold(&s.as_bytes()[original.clone()]);
+            folded.extend_from_slice(&c);
+            offsets.extend(std::iter::repeat_n(original, c.len()));
+        }
+        (folded, offsets)
+    }
+
+    /// Case folds `s` into a lowercase string, for queries
+    pub fn lowercase(&self, s: &str) -> Vec<u8> {
+        self.fold(s.as_bytes()).to_ascii_lowercase()
+    }
+}
+
 pub(crate) fn starts_with_insensitive_ascii(s: &[u8], prefix: &Lowercase) -> bool {
     let prefix = prefix.0;
 
@@ -140,7 +497,7 @@ pub(crate) fn starts_with_insensitive_ascii(s: &[u8], prefix: &Lowercase) -> boo
         return false;
     }
 
-    for (i, b) in prefix.into_iter().enumerate() {
+    for (i, b) in prefix.iter().enumerate() {
         // this is safe because of the earlier if check
         if unsafe { s.get_unchecked(i) }.to_ascii_lowercase() != *b {
             return false;
@@ -151,65 +508,980 @@ pub(crate) fn starts_with_insensitive_ascii(s: &[u8], prefix: &Lowercase) -> boo
 }
 
 pub(crate) fn contains_insensitive_ascii(s: &[u8], inner: &Lowercase) -> bool {
+    find_insensitive_ascii(s, inner).is_some()
+}
+
+/// Range of the first occurrence of `inner` in `s`
+pub(crate) fn find_insensitive_ascii(s: &[u8], inner: &Lowercase) -> Option<Range<usize>> {
     let inner = inner.0;
 
     if s.len() < inner.len() {
-        return false;
+        return None;
     }
 
     'outer: for i in 0..(s.len() - inner.len() + 1) {
         let target = &s[i..i + inner.len()];
-        for (y, b) in target.into_iter().enumerate() {
+        for (y, b) in target.iter().enumerate() {
             if *unsafe { inner.get_unchecked(y) } != b.to_ascii_lowercase() {
                 continue 'outer;
             }
         }
-        return true;
+        return Some(i..i + inner.len());
+    }
+
+    None
+}
+
+/// Ranges of every occurrence of `inner` in `s` that doesn't overlap an earlier one
+pub(crate) fn match_ranges_insensitive_ascii(s: &[u8], inner: &Lowercase) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    if inner.0.is_empty() {
+        return ranges;
+    }
+    let mut start = 0;
+    while let Some(range) = find_insensitive_ascii(&s[start..], inner) {
+        ranges.push(start + range.start..start + range.end);
+        start += range.end;
+    }
+    ranges
+}
+
+/// How well a key matched, and the ranges of it that matched, to highlight them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Match {
+    pub score: u32,
+    /// Sorted, non-overlapping and non-empty byte ranges of the key
+    pub ranges: Vec<Range<usize>>,
+}
+
+impl Match {
+    pub fn new(score: u32, ranges: Vec<Range<usize>>) -> Self {
+        Self {
+            score,
+            ranges: merge_ranges(ranges),
+        }
+    }
+    /// A match of a single range of the key
+    pub fn range(score: u32, range: Range<usize>) -> Self {
+        Self::new(score, vec![range])
+    }
+    /// The same match, with its ranges moved from `key` to the same text in `name`, see
+    /// `align_ranges`
+    pub(crate) fn aligned(self, key: &str, name: &str) -> Self {
+        Self {
+            ranges: align_ranges(key, name, self.ranges),
+            ..self
+        }
+    }
+}
+
+/// Sorts `ranges` and merges the ones that overlap or touch, empty ones are dropped
+pub(crate) fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.retain(|range| !range.is_empty());
+    ranges.sort_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Moves `ranges` of `from` to the same text in `to`, like from `concatStrings` to
+/// `lib.strings.concatStrings`. Ranges in the start or the end the two have in common are
+/// kept, the others are dropped.
+pub(crate) fn align_ranges(from: &str, to: &str, ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    if from == to {
+        return ranges;
+    }
+    let (from, to) = (from.as_bytes(), to.as_bytes());
+    let prefix = from.iter().zip(to).take_while(|(a, b)| a == b).count();
+    let suffix = from
+        .iter()
+        .rev()
+        .zip(to.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count()
+        .min(from.len().min(to.len()) - prefix);
+    let suffix_start = from.len() - suffix;
+    ranges
+        .into_iter()
+        .filter_map(|range| {
+            if range.end <= prefix {
+                Some(range)
+            } else if range.start >= suffix_start {
+                let shift = |i: usize| i - suffix_start + (to.len() - suffix);
+                Some(shift(range.start)..shift(range.end))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+pub(crate) const SCORE_EXACT: u32 = 4000;
+const SCORE_PREFIX: u32 = 3000;
+const SCORE_WHOLE_SEGMENT: u32 = 2500;
+const SCORE_SEGMENT: u32 = 2000;
+const SCORE_ABBREVIATION: u32 = 1500;
+const SCORE_SUBSTRING: u32 = 1000;
+const SCORE_TEXT: u32 = 500;
+
+/// Scores `s` if it starts with `prefix`, an exact match scores the highest
+pub(crate) fn prefix_score_insensitive_ascii(s: &[u8], prefix: &Lowercase) -> Option<Match> {
+    if !starts_with_insensitive_ascii(s, prefix) {
+        None
+    } else if s.len() == prefix.0.len() {
+        Some(Match::range(SCORE_EXACT, 0..prefix.0.len()))
+    } else {
+        Some(Match::range(SCORE_PREFIX, 0..prefix.0.len()))
+    }
+}
+
+/// Scores `s` if it contains `inner`.
+///
+/// From best to worst: exact match, prefix, match at the start of a dotted segment
+/// (`mkif` in `lib.mkIf`, best when it spans the whole segment) and any other substring.
+pub(crate) fn match_score_insensitive_ascii(s: &[u8], inner: &Lowercase) -> Option<Match> {
+    if let Some(m) = prefix_score_insensitive_ascii(s, inner) {
+        return Some(m);
+    }
+
+    let mut segment_match = None;
+    for (i, _) in s.iter().enumerate().filter(|(_, b)| **b == b'.') {
+        let rest = &s[i + 1..];
+        if starts_with_insensitive_ascii(rest, inner) {
+            let range = i + 1..i + 1 + inner.0.len();
+            if rest.get(inner.0.len()).is_none_or(|b| *b == b'.') {
+                return Some(Match::range(SCORE_WHOLE_SEGMENT, range));
+            }
+            segment_match.get_or_insert_with(|| Match::range(SCORE_SEGMENT, range));
+        }
+    }
+
+    segment_match.or_else(|| {
+        find_insensitive_ascii(s, inner).map(|range| Match::range(SCORE_SUBSTRING, range))
+    })
+}
+
+/// Scores `s` if it matches `query` with `fuzzy_distance_insensitive_ascii`.
+/// Typo matches rank below every `match_score_insensitive_ascii` match.
+pub(crate) fn fuzzy_score_insensitive_ascii(s: &[u8], query: &Lowercase) -> Option<Match> {
+    match_score_insensitive_ascii(s, query).or_else(|| {
+        fuzzy_distance_insensitive_ascii(s, query)
+            .map(|(distance, ranges)| Match::new(SCORE_SUBSTRING / (distance as u32 + 2), ranges))
+    })
+}
+
+/// Whether a word starts at `s[i]`: at the start of `s`, after a separator, at an uppercase
+/// letter following a lowercase one, or at a digit following a non-digit
+fn is_word_start(s: &[u8], i: usize) -> bool {
+    let previous = match i.checked_sub(1) {
+        None => return true,
+        Some(previous) => s[previous],
+    };
+    matches!(previous, b'.' | b'-' | b'_')
+        || (s[i].is_ascii_uppercase() && previous.is_ascii_lowercase())
+        || (s[i].is_ascii_digit() && !previous.is_ascii_digit())
+}
+
+/// Matches `s` against the abbreviation `abbreviation`: every character of it is either
+/// right after the previous one in `s`, or at a later word start or dot, so `css` matches
+/// `concatStringsSep` and `s.n.vh` matches `services.nginx.virtualHosts`. The first
+/// character has to be at a word start too.
+///
+/// Returns the ranges of `s` the characters of the abbreviation were matched to.
+pub(crate) fn abbreviation_ranges_insensitive_ascii(
+    s: &[u8],
+    abbreviation: &Lowercase,
+) -> Option<Vec<Range<usize>>> {
+    let can_jump_to = |i: usize| s[i] == b'.' || is_word_start(s, i);
+
+    // `matched[n][i]` is whether the first `n + 1` characters of the abbreviation can end at
+    // `s[i]`
+    let mut matched: Vec<Vec<bool>> = Vec::with_capacity(abbreviation.0.len());
+    let mut previous_row: Vec<bool> = (0..s.len()).map(can_jump_to).collect();
+    for (n, a) in abbreviation.0.iter().enumerate() {
+        let mut row = vec![false; s.len()];
+        let mut any_before = false;
+        for (i, b) in s.iter().enumerate() {
+            let reachable = if n == 0 {
+                previous_row[i]
+            } else {
+                (i > 0 && previous_row[i - 1]) || (any_before && can_jump_to(i))
+            };
+            any_before |= previous_row[i];
+            row[i] = reachable && b.to_ascii_lowercase() == *a;
+        }
+        matched.push(row.clone());
+        previous_row = row;
+    }
+
+    let last = match matched.last() {
+        None => return Some(Vec::new()),
+        Some(last) => last,
+    };
+    // walks back from where the abbreviation ends, preferring runs of characters
+    let mut i = last.iter().position(|m| *m)?;
+    let mut positions = vec![i];
+    for row in matched.iter().rev().skip(1) {
+        i = if i > 0 && row[i - 1] {
+            i - 1
+        } else {
+            (0..i).rev().find(|j| row[*j])?
+        };
+        positions.push(i);
+    }
+    Some(positions.into_iter().map(|i| i..i + 1).collect())
+}
+
+/// Scores `s` if it matches `abbreviation` with `abbreviation_ranges_insensitive_ascii`.
+/// Abbreviations rank below prefix and segment matches, but above plain substrings.
+pub(crate) fn abbreviation_score_insensitive_ascii(
+    s: &[u8],
+    abbreviation: &Lowercase,
+) -> Option<Match> {
+    let m = match_score_insensitive_ascii(s, abbreviation);
+    if m.as_ref().is_some_and(|m| m.score >= SCORE_SEGMENT) {
+        return m;
+    }
+    match abbreviation_ranges_insensitive_ascii(s, abbreviation) {
+        Some(ranges) => Some(Match::new(SCORE_ABBREVIATION, ranges)),
+        None => m,
+    }
+}
+
+/// Compiles a case-insensitive regular expression for `DocSource::search_regex`
+pub fn compile_regex(pattern: &str) -> Result<Regex, Errors> {
+    Ok(RegexBuilder::new(pattern).case_insensitive(true).build()?)
+}
+
+/// Scores `s` if `regex` matches it, by where the first match is, like
+/// `match_score_insensitive_ascii`
+pub(crate) fn regex_score(s: &[u8], regex: &Regex) -> Option<Match> {
+    let m = regex.find(s)?;
+    let at_segment_start = m.start() == 0 || s[m.start() - 1] == b'.';
+    let at_segment_end = s.get(m.end()).is_none_or(|b| *b == b'.');
+
+    let score = match (m.start(), at_segment_start, at_segment_end) {
+        (0, _, _) if m.end() == s.len() => SCORE_EXACT,
+        (0, _, _) => SCORE_PREFIX,
+        (_, true, true) => SCORE_WHOLE_SEGMENT,
+        (_, true, false) => SCORE_SEGMENT,
+        _ => SCORE_SUBSTRING,
+    };
+    Some(Match::range(score, m.range()))
+}
+
+/// A case-insensitive glob over dotted attribute paths, like `services.*.enable`.
+///
+/// It's matched one segment at a time: `*` matches any characters within a segment,
+/// `?` a single one, and a `**` segment matches any number of segments.
+pub struct Glob {
+    pattern: Vec<u8>,
+    segments: Vec<Vec<u8>>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        let pattern = Normalization::default().lowercase(pattern);
+        let segments = pattern.split(|b| *b == b'.').map(|s| s.to_vec()).collect();
+        Self { pattern, segments }
+    }
+
+    /// Longest run of the pattern without wildcards, every matching path contains it
+    pub(crate) fn longest_literal(&self) -> &[u8] {
+        self.pattern
+            .split(|b| *b == b'*' || *b == b'?')
+            .max_by_key(|literal| literal.len())
+            .unwrap_or_default()
+    }
+
+    /// Scores a path given as its segments, all matches are scored the same.
+    ///
+    /// The ranges are the characters matched by the literal parts of the pattern, in the
+    /// segments joined with dots.
+    pub(crate) fn score_segments(&self, path: &[&[u8]]) -> Option<Match> {
+        let normalization = Normalization::default();
+        let folded: Vec<Cow<[u8]>> = path
+            .iter()
+            .map(|segment| normalization.fold(segment))
+            .collect();
+        let folded: Vec<&[u8]> = folded.iter().map(|segment| segment.as_ref()).collect();
+        let positions = glob_match_segments(&self.segments, &folded, 0)?;
+
+        let mut starts = Vec::with_capacity(path.len());
+        let mut start = 0;
+        for segment in path {
+            starts.push(start);
+            start += segment.len() + 1;
+        }
+        let ranges = positions
+            .into_iter()
+            .map(|(n, i)| {
+                let range = if path[n].is_ascii() {
+                    i..i + 1
+                } else {
+                    let segment = String::from_utf8_lossy(path[n]);
+                    normalization.fold_with_offsets(&segment).1[i].clone()
+                };
+                starts[n] + range.start..starts[n] + range.end
+            })
+            .collect();
+        Some(Match::new(SCORE_EXACT, ranges))
+    }
+
+    /// Scores a dotted path, like `lib.strings.concatStrings`
+    pub(crate) fn score_dotted(&self, path: &[u8]) -> Option<Match> {
+        let segments: Vec<&[u8]> = path.split(|b| *b == b'.').collect();
+        self.score_segments(&segments)
+    }
+}
+
+/// Matches `path`, whose first segment is the `first` one of the whole path, against the
+/// segments of a glob. Returns the characters matched by literals, as the index of their
+/// segment and their position in it.
+fn glob_match_segments(
+    pattern: &[Vec<u8>],
+    path: &[&[u8]],
+    first: usize,
+) -> Option<Vec<(usize, usize)>> {
+    match pattern.split_first() {
+        None => path.is_empty().then(Vec::new),
+        Some((p, rest)) if p == b"**" => {
+            (0..=path.len()).find_map(|skip| glob_match_segments(rest, &path[skip..], first + skip))
+        }
+        Some((p, rest)) => {
+            let (segment, path) = path.split_first()?;
+            let mut positions: Vec<(usize, usize)> = glob_match_segment(p, segment)?
+                .into_iter()
+                .map(|i| (first, i))
+                .collect();
+            positions.extend(glob_match_segments(rest, path, first + 1)?);
+            Some(positions)
+        }
+    }
+}
+
+/// Wildcard match of a single segment, `pattern` has to be lowercase.
+/// Returns the positions of the characters matched by literals.
+fn glob_match_segment(pattern: &[u8], s: &[u8]) -> Option<Vec<usize>> {
+    let (mut p, mut i) = (0, 0);
+    let mut matched = Vec::new();
+    // position of the last `*` in the pattern, of `s` when it was reached, and how many
+    // characters were matched then
+    let mut backtrack = None;
+
+    while i < s.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, i, matched.len()));
+                p += 1;
+            }
+            Some(c) if *c == b'?' || *c == s[i].to_ascii_lowercase() => {
+                if *c != b'?' {
+                    matched.push(i);
+                }
+                p += 1;
+                i += 1;
+            }
+            _ => match backtrack {
+                Some((star, star_i, len)) => {
+                    p = star + 1;
+                    i = star_i + 1;
+                    matched.truncate(len);
+                    backtrack = Some((star, star_i + 1, len));
+                }
+                None => return None,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*').then_some(matched)
+}
+
+/// Matches the terms of `query` against the key, or else the text fields.
+///
+/// Returns the match in the key, along with the names of the fields that had a term found
+/// in them.
+pub(crate) fn full_text_match(
+    key: &[u8],
+    entry: &dyn Filterable,
+    fields: &[(&'static str, &str)],
+    query: &Query,
+) -> Option<(Match, Vec<&'static str>)> {
+    fn in_fields<'a>(
+        fields: &'a [(&'static str, Cow<[u8]>)],
+        term: &'a Lowercase,
+    ) -> impl Iterator<Item = &'static str> + 'a {
+        fields
+            .iter()
+            .filter(move |(_, text)| contains_insensitive_ascii(text, term))
+            .map(|(name, _)| *name)
+    }
+
+    let normalization = query.normalization();
+    let fields: Vec<_> = fields
+        .iter()
+        .map(|(name, text)| (*name, normalization.fold(text.as_bytes())))
+        .collect();
+    let fields = &fields[..];
+
+    let (m, terms) = query.score_alternative(key, entry, |key, term| {
+        match_score_insensitive_ascii(key, term).or_else(|| {
+            in_fields(fields, term)
+                .next()
+                .map(|_| Match::new(SCORE_TEXT, Vec::new()))
+        })
+    })?;
+
+    // only the terms that matched, so not the negated ones or those of other alternatives
+    let mut matched_fields = Vec::new();
+    for term in terms {
+        if term.field.is_some() || term.negated {
+            continue;
+        }
+        let term = term.lowercase();
+        let key = normalization.fold(key);
+        if term.0.is_empty() || match_score_insensitive_ascii(&key, &term).is_some() {
+            continue;
+        }
+        for name in in_fields(fields, &term) {
+            if !matched_fields.contains(&name) {
+                matched_fields.push(name);
+            }
+        }
+    }
+
+    Some((m, matched_fields))
+}
+
+/// Length from which `fuzzy_distance_insensitive_ascii` matches queries as subsequences, the
+/// first one with a typo budget
+const MIN_SUBSEQUENCE_QUERY_LEN: usize = 4;
+
+/// Number of typos tolerated by `fuzzy_distance_insensitive_ascii` for a query of this length
+fn fuzzy_typo_budget(query_len: usize) -> usize {
+    query_len / 4
+}
+
+/// Matches `query` against `s` allowing for typos.
+///
+/// Returns the edit distance between `query` and the closest substring of `s`, if it fits
+/// in the typo budget for the query's length, along with the range of that substring.
+/// Queries that are a subsequence of `s`, like `mkenbopt` for `mkEnableOption`, match too,
+/// but rank after every typo match, with the ranges of the characters they matched. Shorter
+/// queries than `MIN_SUBSEQUENCE_QUERY_LEN` don't, as they're a subsequence of most long keys.
+pub(crate) fn fuzzy_distance_insensitive_ascii(
+    s: &[u8],
+    query: &Lowercase,
+) -> Option<(usize, Vec<Range<usize>>)> {
+    let query = query.0;
+    let budget = fuzzy_typo_budget(query.len());
+
+    // Semi-global Levenshtein distance: skipping any prefix or suffix of `s` is free,
+    // so `row[j]` is the cheapest way to end a match of the query so far at `s[..j]`,
+    // along with where in `s` that match starts.
+    let mut row: Vec<(usize, usize)> = (0..=s.len()).map(|j| (0, j)).collect();
+    for (i, q) in query.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = (i + 1, 0);
+        for (j, b) in s.iter().enumerate() {
+            let substitution = (
+                diagonal.0 + (b.to_ascii_lowercase() != *q) as usize,
+                diagonal.1,
+            );
+            diagonal = row[j + 1];
+            let skip_s = (row[j].0 + 1, row[j].1);
+            let skip_query = (diagonal.0 + 1, diagonal.1);
+            row[j + 1] = [substitution, skip_s, skip_query]
+                .iter()
+                .copied()
+                .min_by_key(|(cost, _)| *cost)
+                .unwrap_or(substitution);
+        }
     }
 
-    false
+    let (end, (distance, start)) = row
+        .into_iter()
+        .enumerate()
+        .min_by_key(|(_, (cost, _))| *cost)
+        .unwrap_or((0, (0, 0)));
+    if distance <= budget {
+        let range = start..end;
+        Some((distance, vec![range]))
+    } else if query.len() >= MIN_SUBSEQUENCE_QUERY_LEN {
+        let ranges = subsequence_ranges_insensitive_ascii(s, query)?;
+        Some((budget + 1, merge_ranges(ranges)))
+    } else {
+        None
+    }
+}
+
+/// Ranges of the characters of `s` that `query` is a subsequence of, taking the first ones
+fn subsequence_ranges_insensitive_ascii(s: &[u8], query: &[u8]) -> Option<Vec<Range<usize>>> {
+    let mut s = s.iter().enumerate();
+    query
+        .iter()
+        .map(|q| {
+            s.find(|(_, b)| b.to_ascii_lowercase() == *q)
+                .map(|(i, _)| i..i + 1)
+        })
+        .collect()
 }
 
 #[test]
 fn test_starts_with_insensitive_ascii() {
+    assert!(starts_with_insensitive_ascii(
+        "This is a string".as_bytes(),
+        &Lowercase(b"this ")
+    ));
+    assert!(starts_with_insensitive_ascii(
+        "abc".as_bytes(),
+        &Lowercase(b"abc")
+    ));
+    assert!(!starts_with_insensitive_ascii(
+        "This is a string".as_bytes(),
+        &Lowercase(b"x")
+    ));
+    assert!(!starts_with_insensitive_ascii(
+        "thi".as_bytes(),
+        &Lowercase(b"this ")
+    ));
+}
+
+#[test]
+fn test_contains_insensitive_ascii() {
+    assert!(contains_insensitive_ascii(
+        "abc".as_bytes(),
+        &Lowercase(b"b")
+    ));
+    assert!(contains_insensitive_ascii(
+        "abc".as_bytes(),
+        &Lowercase(b"abc")
+    ));
+    assert!(contains_insensitive_ascii(
+        "xabcx".as_bytes(),
+        &Lowercase(b"abc")
+    ));
+    assert!(!contains_insensitive_ascii(
+        "abc".as_bytes(),
+        &Lowercase(b"x")
+    ));
+    assert!(!contains_insensitive_ascii(
+        "abc".as_bytes(),
+        &Lowercase(b"abcd")
+    ));
+}
+
+#[test]
+fn test_match_ranges_insensitive_ascii() {
+    let ranges =
+        |s: &str, q: &str| match_ranges_insensitive_ascii(s.as_bytes(), &Lowercase(q.as_bytes()));
+    assert_eq!(ranges("lib.mkIf", "mk"), vec![4..6]);
+    assert_eq!(
+        ranges("Nginx nginx NGINX", "nginx"),
+        vec![0..5, 6..11, 12..17]
+    );
+    assert_eq!(ranges("aaaa", "aa"), vec![0..2, 2..4]);
+    assert_eq!(ranges("abc", "x"), Vec::<Range<usize>>::new());
+    assert_eq!(ranges("abc", ""), Vec::<Range<usize>>::new());
+}
+
+#[test]
+fn test_fold_with_offsets() {
+    let strip_accents = Normalization {
+        strip_accents: true,
+    };
     assert_eq!(
-        starts_with_insensitive_ascii("This is a string".as_bytes(), &Lowercase(b"this ")),
-        true,
+        strip_accents.fold_with_offsets("aÉb"),
+        (b"aeb".to_vec(), vec![0..1, 1..3, 3..4])
     );
     assert_eq!(
-        starts_with_insensitive_ascii("abc".as_bytes(), &Lowercase(b"abc")),
-        true,
+        Normalization::default().fold_with_offsets("ß!"),
+        (b"ss!".to_vec(), vec![0..2, 0..2, 2..3])
+    );
+}
+
+#[test]
+fn test_align_ranges() {
+    let align =
+        |from: &str, to: &str, range: Range<usize>| Match::range(0, range).aligned(from, to);
+    assert_eq!(
+        align("concatStrings", "lib.strings.concatStrings", 0..6),
+        Match::range(0, 12..18)
     );
     assert_eq!(
-        starts_with_insensitive_ascii("This is a string".as_bytes(), &Lowercase(b"x")),
-        false,
+        align_ranges(
+            "lib.concatStrings",
+            "lib.strings.concatStrings",
+            vec![0..3, 4..10]
+        ),
+        vec![0..3, 12..18]
     );
     assert_eq!(
-        starts_with_insensitive_ascii("thi".as_bytes(), &Lowercase(b"this ")),
-        false,
+        align("lib.concatStrings", "lib.strings.concatStrings", 2..6),
+        Match::default()
     );
+    assert_eq!(align("lib.mkIf", "lib.mkIf", 4..8), Match::range(0, 4..8));
 }
 
 #[test]
-fn test_contains_insensitive_ascii() {
+fn test_match_score_insensitive_ascii() {
+    let matches =
+        |s: &str, q: &str| match_score_insensitive_ascii(s.as_bytes(), &Lowercase(q.as_bytes()));
+    let score = |s: &str, q: &str| matches(s, q).map(|m| m.score);
+    assert_eq!(score("lib.mkIf", "lib.mkif"), Some(SCORE_EXACT));
+    assert_eq!(score("lib.mkIf", "lib.mk"), Some(SCORE_PREFIX));
+    assert_eq!(score("lib.mkIf", "mkif"), Some(SCORE_WHOLE_SEGMENT));
+    assert_eq!(score("lib.mkIf.x", "mkif"), Some(SCORE_WHOLE_SEGMENT));
+    assert_eq!(score("lib.mkIfNot", "mkif"), Some(SCORE_SEGMENT));
+    assert_eq!(score("lib.optionalMkIf", "mkif"), Some(SCORE_SUBSTRING));
+    assert_eq!(score("lib.mkIf", "mkelse"), None);
+
     assert_eq!(
-        contains_insensitive_ascii("abc".as_bytes(), &Lowercase(b"b")),
-        true
+        matches("lib.mkIf", "lib.mk"),
+        Some(Match::range(SCORE_PREFIX, 0..6))
     );
     assert_eq!(
-        contains_insensitive_ascii("abc".as_bytes(), &Lowercase(b"abc")),
-        true
+        matches("lib.mkIfNot.mkIf", "mkif"),
+        Some(Match::range(SCORE_WHOLE_SEGMENT, 12..16))
     );
     assert_eq!(
-        contains_insensitive_ascii("xabcx".as_bytes(), &Lowercase(b"abc")),
-        true
+        matches("lib.mkIfNot.x", "mkif"),
+        Some(Match::range(SCORE_SEGMENT, 4..8))
     );
     assert_eq!(
-        contains_insensitive_ascii("abc".as_bytes(), &Lowercase(b"x")),
-        false
+        matches("lib.optionalMkIf", "mkif"),
+        Some(Match::range(SCORE_SUBSTRING, 12..16))
+    );
+}
+
+#[test]
+fn test_fuzzy_score_insensitive_ascii() {
+    let matches =
+        |s: &str, q: &str| fuzzy_score_insensitive_ascii(s.as_bytes(), &Lowercase(q.as_bytes()));
+    let score = |s: &str, q: &str| matches(s, q).map(|m| m.score);
+    assert_eq!(score("lib.mkIf", "mkif"), Some(SCORE_WHOLE_SEGMENT));
+    assert!(score("concatStringsSep", "concatstrinssep").unwrap() < SCORE_SUBSTRING);
+    assert!(score("concatStr", "conctstr") > score("c.o.n.c.t.s.t.r", "conctstr"));
+    assert_eq!(score("concatStringsSep", "mkif"), None);
+
+    assert_eq!(
+        matches("lib.concatStringsSep", "conctstrings"),
+        Some(Match::range(SCORE_SUBSTRING / 3, 4..17))
+    );
+    assert_eq!(
+        matches("lib.mkEnableOption", "mkenbopt").map(|m| m.ranges),
+        Some(vec![4..8, 9..10, 12..15])
+    );
+}
+
+#[test]
+fn test_abbreviation_score_insensitive_ascii() {
+    let matches = |s: &str, q: &str| {
+        abbreviation_score_insensitive_ascii(s.as_bytes(), &Lowercase(q.as_bytes()))
+    };
+    let score = |s: &str, q: &str| matches(s, q).map(|m| m.score);
+    assert_eq!(score("concatStringsSep", "css"), Some(SCORE_ABBREVIATION));
+    assert_eq!(
+        score("services.nginx.virtualHosts", "s.n.vh"),
+        Some(SCORE_ABBREVIATION)
+    );
+    assert_eq!(
+        score("lib.mkEnableOption", "mkeo"),
+        Some(SCORE_ABBREVIATION)
+    );
+    assert_eq!(
+        score("lib.mkEnableOption", "lmeo"),
+        Some(SCORE_ABBREVIATION)
+    );
+    assert_eq!(score("lib.mkEnableOption", "mkenable"), Some(SCORE_SEGMENT));
+    assert_eq!(score("lib.mkEnableOption", "nable"), Some(SCORE_SUBSTRING));
+    assert_eq!(score("lib.mkEnableOption", "enbo"), None);
+    assert_eq!(score("lib.mkEnableOption", "keo"), None);
+    assert_eq!(score("concatStringsSep", "csss"), None);
+    assert_eq!(score("services.nginx.virtualHosts", "s..n"), None);
+
+    let ranges = |s: &str, q: &str| matches(s, q).map(|m| m.ranges);
+    assert_eq!(
+        ranges("concatStringsSep", "css"),
+        Some(vec![0..1, 6..7, 13..14])
     );
     assert_eq!(
-        contains_insensitive_ascii("abc".as_bytes(), &Lowercase(b"abcd")),
-        false
+        ranges("services.nginx.virtualHosts", "s.n.vh"),
+        Some(vec![0..1, 8..10, 14..16, 22..23])
+    );
+    assert_eq!(
+        ranges("lib.mkEnableOption", "mkeo"),
+        Some(vec![4..7, 12..13])
+    );
+}
+
+#[test]
+fn test_regex_score() {
+    let matches =
+        |s: &str, pattern: &str| regex_score(s.as_bytes(), &compile_regex(pattern).unwrap());
+    let score = |s: &str, pattern: &str| matches(s, pattern).map(|m| m.score);
+    assert_eq!(
+        score(
+            "services.nginx.virtualHosts.<name>.root",
+            r"^services\.(nginx|caddy)\..*\.root$"
+        ),
+        Some(SCORE_EXACT)
+    );
+    assert_eq!(
+        score("lib.strings.concatStringsSep", r"^lib\.strings\..*Sep"),
+        Some(SCORE_EXACT)
+    );
+    assert_eq!(
+        score("lib.strings.concatStrings", r"^lib\.strings\..*Sep"),
+        None
+    );
+    assert_eq!(score("lib.mkIf", "mk(if|merge)"), Some(SCORE_WHOLE_SEGMENT));
+    assert_eq!(score("lib.mkIfNot", "MK"), Some(SCORE_SEGMENT));
+    assert_eq!(score("lib.optionalMkIf", "mk"), Some(SCORE_SUBSTRING));
+    assert!(matches!(compile_regex("lib.(mkIf"), Err(Errors::Regex(_))));
+
+    assert_eq!(
+        matches("lib.concatStringsSep", "sep$"),
+        Some(Match::range(SCORE_SUBSTRING, 17..20))
+    );
+}
+
+#[test]
+fn test_glob() {
+    let matches =
+        |pattern: &str, path: &str| Glob::new(pattern).score_dotted(path.as_bytes()).is_some();
+    assert!(matches("services.*.enable", "services.nginx.enable"));
+    assert!(!matches("services.*.enable", "services.nginx.ssl.enable"));
+    assert!(!matches("services.*.enable", "services.nginx.enableReload"));
+    assert!(matches("programs.**.package", "programs.package"));
+    assert!(matches("programs.**.package", "programs.git.delta.package"));
+    assert!(matches("lib.*.concat*", "lib.strings.concatStringsSep"));
+    assert!(matches("lib.*.concat*", "lib.Strings.ConcatMap"));
+    assert!(!matches("lib.*.concat*", "lib.concatMap"));
+    assert!(matches("lib.?kIf", "lib.mkIf"));
+    assert!(matches("**", "anything.at.all"));
+
+    assert_eq!(
+        Glob::new("services.*.enable").longest_literal(),
+        b"services."
+    );
+    assert_eq!(Glob::new("lib.*.concat*").longest_literal(), b".concat");
+
+    let ranges = |pattern: &str, path: &str| {
+        Glob::new(pattern)
+            .score_dotted(path.as_bytes())
+            .map(|m| m.ranges)
+    };
+    assert_eq!(
+        ranges("services.*.enable", "services.nginx.enable"),
+        Some(vec![0..8, 15..21])
+    );
+    assert_eq!(
+        ranges("lib.*.concat*", "lib.strings.concatStringsSep"),
+        Some(vec![0..3, 12..18])
+    );
+    assert_eq!(
+        ranges("programs.**.pack?ge", "programs.git.delta.package"),
+        Some(vec![0..8, 19..23, 24..26])
+    );
+    assert_eq!(
+        ranges("programs.*.école", "programs.x.École"),
+        Some(vec![0..8, 11..17])
+    );
+}
+
+#[test]
+fn test_full_text_match() {
+    let fields = [
+        ("description", "Whether to open ports in the firewall."),
+        ("example", "[ 80 443 ]"),
+    ];
+    let matches = |q: &str| {
+        full_text_match(
+            b"networking.firewall.allowedTCPPorts",
+            &Vec::new(),
+            &fields,
+            &Query::parse(q),
+        )
+    };
+    let search = |q: &str| matches(q).map(|(m, fields)| (m.score, fields));
+    assert_eq!(search("firewall"), Some((SCORE_WHOLE_SEGMENT, vec![])));
+    assert_eq!(
+        search("whether open"),
+        Some((SCORE_TEXT, vec!["description"]))
+    );
+    assert_eq!(
+        search("open port"),
+        Some(((SCORE_TEXT + SCORE_SUBSTRING) / 2, vec!["description"]))
+    );
+    assert_eq!(
+        search("tcpports 443"),
+        Some(((SCORE_SUBSTRING + SCORE_TEXT) / 2, vec!["example"]))
+    );
+    assert_eq!(search("open udp"), None);
+    assert_eq!(search("open -udp"), Some((SCORE_TEXT, vec!["description"])));
+    assert_eq!(search("open -tcp"), None);
+    assert_eq!(
+        search("\"in the firewall\""),
+        Some((SCORE_TEXT, vec!["description"]))
+    );
+    assert_eq!(search("udp OR 443"), Some((SCORE_TEXT, vec!["example"])));
+    // only the positive terms of the alternative that matched count
+    assert_eq!(
+        search("whether udp OR 443"),
+        Some((SCORE_TEXT, vec!["example"]))
+    );
+    assert_eq!(
+        search("open -whether OR 443"),
+        Some((SCORE_TEXT, vec!["example"]))
+    );
+    assert_eq!(
+        search("443 -udp OR udp"),
+        Some((SCORE_TEXT, vec!["example"]))
+    );
+    assert_eq!(
+        matches("tcpports 443").map(|(m, _)| m),
+        Some(Match::range((SCORE_SUBSTRING + SCORE_TEXT) / 2, 27..35))
+    );
+}
+
+#[test]
+fn test_fuzzy_distance_insensitive_ascii() {
+    let distance = |s: &[u8], q: &Lowercase| fuzzy_distance_insensitive_ascii(s, q).map(|(d, _)| d);
+    assert_eq!(
+        distance(b"lib.concatStringsSep", &Lowercase(b"concatstringssep")),
+        Some(0)
+    );
+    assert_eq!(
+        distance(b"concatStringsSep", &Lowercase(b"concatstrinssep")),
+        Some(1)
+    );
+    assert_eq!(
+        distance(b"mkEnableOption", &Lowercase(b"mkenabeloption")),
+        Some(2)
+    );
+    assert_eq!(
+        distance(b"mkEnableOption", &Lowercase(b"mkenbopt")),
+        Some(3)
+    );
+    // short queries are a subsequence of too many keys
+    assert_eq!(distance(b"concatStringsSep", &Lowercase(b"css")), None);
+    assert_eq!(distance(b"mkIf", &Lowercase(b"mkenabeloption")), None);
+    assert_eq!(distance(b"abc", &Lowercase(b"abx")), None);
+
+    let (_, ranges) =
+        fuzzy_distance_insensitive_ascii(b"lib.concatStringsSep", &Lowercase(b"concatstrinssep"))
+            .unwrap();
+    assert_eq!(ranges, vec![4..20]);
+    assert_eq!(
+        fuzzy_distance_insensitive_ascii(b"mkEnableOption", &Lowercase(b"mkenbopt")),
+        Some((3, vec![0..4, 5..6, 8..11]))
+    );
+}
+
+#[test]
+fn test_top_results() {
+    let matches: Vec<(Match, &str)> = [(10, "a"), (40, "b"), (20, "c"), (40, "d"), (30, "e")]
+        .iter()
+        .map(|(score, name)| (Match::new(*score, vec![]), *name))
+        .collect();
+    let top = |limit| {
+        let mut names: Vec<String> = top_results(matches.clone(), limit, |m, name| {
+            SearchResult::new(DocEntry::NixpkgsTreeDoc(name.to_owned()), m.score)
+        })
+        .into_iter()
+        .map(|r| r.entry.name())
+        .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(top(None), vec!["a", "b", "c", "d", "e"]);
+    assert_eq!(top(Some(10)), vec!["a", "b", "c", "d", "e"]);
+    assert_eq!(top(Some(3)), vec!["b", "d", "e"]);
+    assert_eq!(top(Some(1)).len(), 1);
+    assert_eq!(top(Some(0)), Vec::<String>::new());
+}
+
+#[test]
+fn test_merge_duplicates() {
+    use comments_docsource::CommentDocumentation;
+
+    let comment = |key: &str, path: &str| {
+        DocEntry::CommentDoc(
+            CommentDocumentation::new(key.to_owned(), vec![]).with_path(path.into()),
+        )
+    };
+    let tree = |key: &str| DocEntry::NixpkgsTreeDoc(key.to_owned());
+    let manual = DocEntry::XmlFuncDoc(xml_docsource::XmlFuncDocumentation {
+        name: "lib.strings.concatStrings".to_owned(),
+        description: String::new(),
+        fn_type: None,
+        args: Vec::new(),
+        example: None,
+        location: Some("lib/strings.nix:49".to_owned()),
+    });
+    let results = merge_duplicates(vec![
+        SearchResult::new(tree("lib.strings.concatStrings"), 4000),
+        SearchResult::new(manual, 2500),
+        SearchResult::new(comment("concatStrings", "lib/strings.nix"), 2500),
+        SearchResult::new(comment("concatStrings", "pkgs/strings.nix"), 2500),
+        SearchResult::new(tree("lib.concatStrings"), 2000),
+        SearchResult::new(comment("isInt", "lib/default.nix"), 1000),
+        SearchResult::new(tree("lib.isInt"), 1000),
+        SearchResult::new(comment("isInt", "lib/trivial.nix"), 500),
+    ]);
+
+    assert_eq!(
+        results
+            .iter()
+            .map(|r| (r.entry.name(), r.sources(), r.score))
+            .collect::<Vec<_>>(),
+        vec![
+            // listed by the tree under both of its names, the manual and the comments
+            (
+                "lib.strings.concatStrings".to_owned(),
+                vec!["Nixpkgs Documentation", "Nixpkgs Tree", "Nixpkgs Comments"],
+                4000
+            ),
+            ("concatStrings".to_owned(), vec!["Nixpkgs Comments"], 2530),
+            (
+                "lib.isInt".to_owned(),
+                vec!["Nixpkgs Comments", "Nixpkgs Tree"],
+                1030
+            ),
+            (
+                "lib.trivial.isInt".to_owned(),
+                vec!["Nixpkgs Comments"],
+                530
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_ordering_key() {
+    use comments_docsource::CommentDocumentation;
+
+    let comment =
+        |key: &str| DocEntry::CommentDoc(CommentDocumentation::new(key.to_owned(), vec![]));
+    let tree = |key: &str| DocEntry::NixpkgsTreeDoc(key.to_owned());
+    // the comments get 30 more for their source, it's a tie
+    let mut results = [
+        SearchResult::new(tree("lib.b"), 1030),
+        SearchResult::new(tree("lib.a"), 1030),
+        SearchResult::new(comment("b"), 1000),
+        SearchResult::new(comment("a"), 1000),
+        SearchResult::new(tree("lib.c"), 2000),
+    ];
+    results.sort_by_cached_key(SearchResult::ordering_key);
+    assert_eq!(
+        results.iter().map(|r| r.entry.name()).collect::<Vec<_>>(),
+        vec!["lib.c", "a", "b", "lib.a", "lib.b"]
     );
 }
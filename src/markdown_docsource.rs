@@ -0,0 +1,391 @@
+use crate::{
+    xml_docsource::{DocBook, FuncDocDatabase, FuncDocLoader, XmlFuncDocumentation},
+    Errors,
+};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+use walkdir::WalkDir;
+
+/// Subsections of a function's documentation, from headings like `# Inputs` or `### Example`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Description,
+    Type,
+    Inputs,
+    Example,
+    Other,
+}
+
+impl Part {
+    fn from_heading(heading: &str) -> Self {
+        let title = heading.trim_start_matches('#');
+        let title = title.split("{#").next().unwrap_or(title).trim();
+        match title.to_lowercase().as_str() {
+            "type" => Part::Type,
+            "inputs" | "arguments" | "function arguments" => Part::Inputs,
+            "example" | "examples" => Part::Example,
+            _ => Part::Other,
+        }
+    }
+}
+
+/// Name of the function documented in a section, from a heading like
+/// ``## `lib.strings.concatStrings` {#function-library-lib.strings.concatStrings}``
+fn function_heading(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("## ")?.trim().strip_prefix('`')?;
+    let (name, anchor) = rest.split_once('`')?;
+    let anchor = anchor.trim();
+    (!name.is_empty() && (anchor.is_empty() || anchor.starts_with("{#") && anchor.ends_with('}')))
+        .then_some(name)
+}
+
+/// Tracks whether lines are in a code block or a `:::` block, like the examples
+#[derive(Debug, Default)]
+struct Blocks {
+    code: bool,
+    fenced_divs: usize,
+}
+
+impl Blocks {
+    /// Updates the state with `line`, returns whether it opened or closed a block
+    fn delimits(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        if line.starts_with("```") {
+            self.code = !self.code;
+            true
+        } else if !self.code && line.starts_with(":::") {
+            if line == ":::" {
+                self.fenced_divs = self.fenced_divs.saturating_sub(1);
+            } else {
+                self.fenced_divs += 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Splits nixdoc's markdown into the names of the functions it documents, and their sections
+fn function_sections(markdown: &str) -> Vec<(&str, Vec<&str>)> {
+    let mut sections: Vec<(&str, Vec<&str>)> = Vec::new();
+    let mut blocks = Blocks::default();
+    for line in markdown.lines() {
+        let delimiter = blocks.delimits(line);
+        if !delimiter && !blocks.code && blocks.fenced_divs == 0 {
+            if let Some(name) = function_heading(line) {
+                sections.push((name, Vec::new()));
+                continue;
+            }
+        }
+        if let Some((_, lines)) = sections.last_mut() {
+            lines.push(line);
+        }
+    }
+    sections
+}
+
+/// Splits `lines` into text and a definition list of arguments, like
+///
+/// ```markdown
+/// `sep`
+/// : Separator to add between elements
+/// ```
+fn split_arguments(lines: &[&str]) -> (Vec<String>, Vec<(String, String)>) {
+    let mut text = Vec::new();
+    let mut args: Vec<(String, String)> = Vec::new();
+    let mut pending_name: Option<&str> = None;
+    // whether the lines continue the last argument's description
+    let mut in_argument = false;
+    for line in lines {
+        let trimmed = line.trim();
+        let lone_name = trimmed
+            .strip_prefix('`')
+            .and_then(|s| s.strip_suffix('`'))
+            .filter(|name| !name.is_empty() && !name.contains('`'));
+        if let Some(name) = lone_name {
+            if let Some(pending) = pending_name.replace(name) {
+                text.push(format!("`{}`", pending));
+            }
+            in_argument = false;
+        } else if let Some(description) = trimmed.strip_prefix(": ") {
+            match pending_name.take() {
+                Some(name) => {
+                    args.push((name.to_owned(), description.trim().to_owned()));
+                    in_argument = true;
+                }
+                None => text.push((*line).to_owned()),
+            }
+        } else if trimmed.is_empty() {
+            if pending_name.is_none() {
+                in_argument = false;
+                text.push(String::new());
+            }
+        } else if in_argument && line.starts_with(char::is_whitespace) {
+            let (_, description) = args.last_mut().unwrap();
+            description.push(' ');
+            description.push_str(trimmed);
+        } else {
+            if let Some(pending) = pending_name.take() {
+                text.push(format!("`{}`", pending));
+            }
+            in_argument = false;
+            text.push((*line).to_owned());
+        }
+    }
+    if let Some(pending) = pending_name {
+        text.push(format!("`{}`", pending));
+    }
+    for (_, description) in &mut args {
+        // nixdoc escapes the dot of numbered arguments, like `1\. Function argument`
+        *description = description.replace("\\.", ".");
+    }
+    (text, args)
+}
+
+/// Contents of the first code block in `lines`
+fn first_code_block(lines: &[&str]) -> Option<String> {
+    let start = lines
+        .iter()
+        .position(|l| l.trim_start().starts_with("```"))?
+        + 1;
+    let length = lines[start..]
+        .iter()
+        .position(|l| l.trim_start().starts_with("```"))?;
+    Some(lines[start..start + length].join("\n"))
+}
+
+/// Joins `lines` into paragraphs, without the blank lines around them
+fn paragraphs(lines: &[String]) -> String {
+    lines.join("\n").trim().to_owned()
+}
+
+/// Parses the section of the function `name`, split off by `function_sections`.
+/// RFC 145 doc comments follow the same conventions.
+pub(crate) fn parse_function(name: &str, lines: &[&str]) -> XmlFuncDocumentation {
+    let mut parts: Vec<(Part, Vec<&str>)> = vec![(Part::Description, Vec::new())];
+    let mut fn_type = None;
+    let mut location = None;
+    let mut blocks = Blocks::default();
+    // part the current `::: {.example}` block interrupted
+    let mut before_example = None;
+    for line in lines {
+        let fenced_divs = blocks.fenced_divs;
+        let delimiter = blocks.delimits(line);
+        if delimiter && blocks.fenced_divs != fenced_divs {
+            if blocks.fenced_divs > fenced_divs && line.contains(".example") {
+                before_example = parts.last().map(|(part, _)| *part);
+                parts.push((Part::Example, Vec::new()));
+            } else if blocks.fenced_divs == 0 {
+                if let Some(part) = before_example.take() {
+                    parts.push((part, Vec::new()));
+                }
+            }
+            continue;
+        }
+        if !delimiter && !blocks.code {
+            if line.starts_with('#') {
+                // headings of examples only repeat the name
+                if blocks.fenced_divs == 0 {
+                    parts.push((Part::from_heading(line), Vec::new()));
+                }
+                continue;
+            }
+            if let Some(signature) = line.strip_prefix("**Type**:") {
+                fn_type = Some(signature.trim().trim_matches('`').to_owned());
+                continue;
+            }
+            if let Some(link) = line.strip_prefix("Located at [") {
+                location = link.split_once(']').map(|(file, _)| file.to_owned());
+                continue;
+            }
+        }
+        parts.last_mut().unwrap().1.push(line);
+    }
+
+    let mut description = Vec::new();
+    let mut args = Vec::new();
+    let mut example = None;
+    for (part, lines) in &parts {
+        match part {
+            Part::Description | Part::Inputs => {
+                let (text, mut part_args) = split_arguments(lines);
+                if *part == Part::Description {
+                    description.extend(text);
+                }
+                args.append(&mut part_args);
+            }
+            Part::Type if fn_type.is_none() => {
+                // usually a code block, but inline code is seen too
+                let signature = first_code_block(lines)
+                    .unwrap_or_else(|| lines.join(" ").trim().trim_matches('`').to_owned());
+                fn_type = Some(signature.trim().to_owned()).filter(|s| !s.is_empty());
+            }
+            Part::Example if example.is_none() => example = first_code_block(lines),
+            _ => (),
+        }
+    }
+
+    XmlFuncDocumentation {
+        name: name.to_owned(),
+        description: paragraphs(&description),
+        fn_type,
+        args,
+        example,
+        location,
+    }
+}
+
+/// Functions documented in a markdown file generated by nixdoc
+fn functions_in(markdown: &str) -> Vec<XmlFuncDocumentation> {
+    function_sections(markdown)
+        .into_iter()
+        .map(|(name, lines)| parse_function(name, &lines))
+        .collect()
+}
+
+/// The CommonMark documentation nixdoc generates for current nixpkgs
+#[derive(Debug, Clone, Copy)]
+pub struct Nixdoc;
+
+impl FuncDocLoader for Nixdoc {
+    fn load() -> Result<Vec<XmlFuncDocumentation>, Errors> {
+        functions_in_dir(&generate_docs()?)
+    }
+}
+
+pub type MarkdownFuncDocDatabase = FuncDocDatabase<Nixdoc>;
+
+/// Documentation of nixpkgs' functions, from its markdown or, for older nixpkgs, its DocBook
+pub type NixpkgsDocDatabase = FuncDocDatabase<(Nixdoc, DocBook)>;
+
+impl<L> FuncDocDatabase<L> {
+    /// Reads the markdown files of a directory generated by nixdoc, like the `lib-docs` of
+    /// nixpkgs' manual, instead of generating them. Returns whether the documented functions
+    /// changed.
+    pub fn update_from(&mut self, path: &Path) -> Result<bool, Errors> {
+        Ok(self.replace_functions(functions_in_dir(path)?))
+    }
+}
+
+/// Functions documented in the markdown files of a directory
+fn functions_in_dir(path: &Path) -> Result<Vec<XmlFuncDocumentation>, Errors> {
+    let mut functions = Vec::new();
+    for file in markdown_files_in(path) {
+        let content = std::fs::read_to_string(&file).map_err(|e| Errors::FileIo {
+            err: e,
+            filename: file.to_string_lossy().into_owned(),
+        })?;
+        functions.extend(functions_in(&content));
+    }
+    Ok(functions)
+}
+
+fn markdown_files_in(path: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| !e.file_type().is_dir())
+        .filter(|e| e.path().extension().and_then(|s| s.to_str()) == Some("md"))
+        .map(|x| x.path().to_path_buf())
+        .collect();
+    files.sort();
+    files
+}
+
+/// Builds the markdown documentation of `lib` from the nixpkgs channel
+fn generate_docs() -> Result<PathBuf, Errors> {
+    let output = Command::new("nix-build")
+        .arg("--no-out-link")
+        .arg("<nixpkgs/doc>")
+        .arg("-A")
+        .arg("lib-docs")
+        .output()?;
+    if !output.status.success() {
+        return Err(Errors::DocBuild(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    let doc_path = String::from_utf8_lossy(&output.stdout);
+    Ok(PathBuf::from(doc_path.trim_end_matches('\n')))
+}
+
+#[test]
+fn test_update_from() {
+    use crate::{query::Query, signature::Signature, DocSource};
+
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/nixdoc");
+    let mut database = MarkdownFuncDocDatabase::new();
+    assert!(database.update_from(&fixtures).unwrap());
+    assert!(!database.update_from(&fixtures).unwrap());
+    assert_eq!(
+        database.all_keys(),
+        vec![
+            "lib.lists.foldr",
+            "lib.strings.concatStrings",
+            "lib.strings.concatStringsSep",
+            "lib.strings.optionalString",
+        ]
+    );
+
+    let function = |name: &str| &database.functions[name];
+    assert_eq!(
+        function("lib.strings.concatStringsSep"),
+        &XmlFuncDocumentation {
+            name: "lib.strings.concatStringsSep".to_owned(),
+            description: "Concatenate a list of strings with a separator between each\n\
+                element, e.g. `concatStringsSep \"/\" [\"usr\" \"local\" \"bin\"] == \"usr/local/bin\"`."
+                .to_owned(),
+            fn_type: Some("concatStringsSep :: string -> [string] -> string".to_owned()),
+            args: vec![
+                ("sep".to_owned(), "Separator to add between elements".to_owned()),
+                ("list".to_owned(), "List of input strings".to_owned()),
+            ],
+            example: Some(
+                "concatStringsSep \"/\" [\"usr\" \"local\" \"bin\"]\n=> \"usr/local/bin\"".to_owned()
+            ),
+            location: Some("lib/strings.nix:128".to_owned()),
+        }
+    );
+    assert_eq!(
+        function("lib.strings.optionalString").args,
+        vec![
+            ("cond".to_owned(), "Condition".to_owned()),
+            (
+                "string".to_owned(),
+                "String to return if condition is true and the empty string otherwise".to_owned()
+            ),
+        ]
+    );
+    assert_eq!(function("lib.strings.optionalString").example, None);
+
+    let foldr = function("lib.lists.foldr");
+    assert_eq!(
+        foldr.fn_type.as_deref(),
+        Some("foldr :: (a -> b -> b) -> b -> [a] -> b")
+    );
+    assert!(foldr.description.starts_with("“right fold”"));
+    assert!(foldr.description.ends_with("(op x_n nul))`."));
+    assert_eq!(
+        foldr
+            .args
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["op", "nul", "list"]
+    );
+    assert_eq!(foldr.args[0].1, "1. Function argument");
+    assert_eq!(
+        foldr.example.as_deref(),
+        Some("concat = foldr (a: b: a + b) \"z\"\nconcat [ \"a\" \"b\" \"c\" ]\n=> \"abcz\"")
+    );
+    assert_eq!(foldr.defining_file(), Some(PathBuf::from("lib/lists.nix")));
+
+    let results = database.search(&Query::parse("lib.strings.concat"), None);
+    assert_eq!(results.len(), 2);
+    let signature = Signature::parse("[string] -> string").unwrap();
+    let results = database.search_type(&signature, Some(1));
+    assert_eq!(results[0].entry.name(), "lib.strings.concatStrings");
+}
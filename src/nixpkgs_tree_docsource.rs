@@ -1,31 +1,87 @@
 use crate::{
-    contains_insensitive_ascii, starts_with_insensitive_ascii, Cache, DocEntry, DocSource, Errors,
-    Lowercase,
+    abbreviation_score_insensitive_ascii, full_text_match, fuzzy_score_insensitive_ascii,
+    index::{fst_strings, SearchIndex},
+    match_score_insensitive_ascii, prefix_score_insensitive_ascii,
+    query::Query,
+    regex_score,
+    signature::Signature,
+    top_results, Cache, DocEntry, DocSource, Errors, Filterable, Glob, Match, SearchResult,
+    SOURCE_NIXPKGS_TREE,
 };
+use regex::bytes::Regex;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, process::Command};
+use std::{borrow::Cow, collections::HashMap, process::Command};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NixpkgsTreeDatabase {
+    /// Sorted, so they can be stored compactly
+    #[serde(with = "fst_strings")]
     keys: Vec<String>,
+    index: SearchIndex<u32>,
+}
+
+impl Default for NixpkgsTreeDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NixpkgsTreeDatabase {
     pub fn new() -> Self {
-        Self { keys: Vec::new() }
+        Self {
+            keys: Vec::new(),
+            index: SearchIndex::default(),
+        }
+    }
+
+    fn build_index(&mut self) {
+        self.index = self
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (i as u32, key.as_str(), Vec::new()))
+            .collect();
+    }
+
+    /// Keys at the given positions, or all of them if the index couldn't narrow them down
+    fn candidates(&self, positions: Option<Vec<&u32>>) -> Vec<&String> {
+        match positions {
+            Some(positions) => positions
+                .into_iter()
+                .map(|i| &self.keys[*i as usize])
+                .collect(),
+            None => self.keys.iter().collect(),
+        }
+    }
+
+    fn search_with(
+        &self,
+        limit: Option<usize>,
+        positions: Option<Vec<&u32>>,
+        score: impl Fn(&[u8]) -> Option<Match>,
+    ) -> Vec<SearchResult> {
+        let matches = self
+            .candidates(positions)
+            .into_iter()
+            .filter_map(|k| Some((score(k.as_bytes())?, k)))
+            .collect();
+        top_results(matches, limit, |m, k| {
+            SearchResult::new(DocEntry::NixpkgsTreeDoc(k.clone()), m.score)
+                .with_name_ranges(m.ranges)
+        })
     }
 }
 
 #[derive(Serialize, Deserialize)]
 struct Keys(HashMap<String, Keys>);
 
-impl Into<Vec<String>> for Keys {
-    fn into(self) -> Vec<String> {
+impl From<Keys> for Vec<String> {
+    fn from(keys: Keys) -> Self {
         let mut res = Vec::<String>::new();
-        for (mut name, keys) in self.0 {
+        for (mut name, keys) in keys.0 {
             res.push(name.clone());
             name.push('.');
-            for key in Into::<Vec<String>>::into(keys) {
+            for key in Vec::<String>::from(keys) {
                 let mut name = name.clone();
                 name.push_str(&key);
                 res.push(name);
@@ -35,27 +91,78 @@ impl Into<Vec<String>> for Keys {
     }
 }
 
+/// Fields of every key in the tree, the keys have nothing but their name
+impl Filterable for NixpkgsTreeDatabase {
+    fn filter_field(&self, field: &str) -> Option<Cow<'_, str>> {
+        match field {
+            "source" => Some(Cow::from(SOURCE_NIXPKGS_TREE)),
+            _ => None,
+        }
+    }
+}
+
 impl DocSource for NixpkgsTreeDatabase {
+    fn sources(&self) -> Vec<&str> {
+        vec![SOURCE_NIXPKGS_TREE]
+    }
     fn all_keys(&self) -> Vec<&str> {
         self.keys.iter().map(|k| k.as_str()).collect()
     }
-    fn search(&self, query: &Lowercase) -> Vec<DocEntry> {
-        self.keys
-            .iter()
-            .filter(|k| starts_with_insensitive_ascii(k.as_bytes(), query))
-            .map(|k| DocEntry::NixpkgsTreeDoc(k.clone()))
-            .collect()
+    fn search(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, self.index.prefix_candidates(query), |key| {
+            query.score(key, self, prefix_score_insensitive_ascii)
+        })
     }
-    fn search_liberal(&self, query: &Lowercase) -> Vec<DocEntry> {
-        self.keys
-            .iter()
-            .filter(|k| contains_insensitive_ascii(k.as_bytes(), query))
-            .map(|k| DocEntry::NixpkgsTreeDoc(k.clone()))
-            .collect()
+    fn search_liberal(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, self.index.key_candidates(query), |key| {
+            query.score(key, self, match_score_insensitive_ascii)
+        })
+    }
+    fn search_fuzzy(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |key| {
+            query.score(key, self, fuzzy_score_insensitive_ascii)
+        })
+    }
+    fn search_abbreviation(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |key| {
+            query.score(key, self, abbreviation_score_insensitive_ascii)
+        })
+    }
+    fn search_regex(&self, regex: &Regex, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |key| regex_score(key, regex))
+    }
+    fn search_glob(&self, glob: &Glob, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(
+            limit,
+            self.index.literal_candidates(glob.longest_literal()),
+            |key| glob.score_dotted(key),
+        )
+    }
+    fn lookup(&self, name: &str) -> Vec<SearchResult> {
+        // the keys are sorted
+        match self.keys.binary_search_by(|k| k.as_str().cmp(name)) {
+            Ok(i) => vec![SearchResult::exact(DocEntry::NixpkgsTreeDoc(
+                self.keys[i].clone(),
+            ))],
+            Err(_) => Vec::new(),
+        }
+    }
+    fn search_type(&self, _: &Signature, _: Option<usize>) -> Vec<SearchResult> {
+        // the tree only has keys
+        Vec::new()
+    }
+    fn search_full_text(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        // the tree only has keys, there's no text to search
+        self.search_with(limit, self.index.full_text_candidates(query), |key| {
+            full_text_match(key, self, &[], query).map(|(m, _)| m)
+        })
     }
     fn update(&mut self) -> Result<bool, Errors> {
-        let new_keys = gen_keys()?;
+        let mut new_keys = gen_keys()?;
+        new_keys.sort_unstable();
+        new_keys.dedup();
         let old = std::mem::replace(&mut self.keys, new_keys);
+        self.build_index();
 
         Ok(old != self.keys)
     }
@@ -90,5 +197,28 @@ in
 
     let keys = serde_json::from_slice::<Keys>(&command.stdout)?;
 
-    Ok(Into::<Vec<String>>::into(keys))
+    Ok(keys.into())
+}
+
+#[test]
+fn test_lookup() {
+    let mut database = NixpkgsTreeDatabase::new();
+    database.keys = vec![
+        "lib".to_owned(),
+        "lib.mkIf".to_owned(),
+        "lib.mkIfThen".to_owned(),
+    ];
+    database.build_index();
+    let lookup = |name: &str| -> Vec<String> {
+        database
+            .lookup(name)
+            .into_iter()
+            .map(|result| result.entry.name())
+            .collect()
+    };
+
+    assert_eq!(lookup("lib.mkIf"), vec!["lib.mkIf"]);
+    assert_eq!(lookup("lib"), vec!["lib"]);
+    assert!(lookup("lib.mk").is_empty());
+    assert!(lookup("lib.mkif").is_empty());
 }
@@ -1,16 +1,15 @@
 use crate::{
-    contains_insensitive_ascii, starts_with_insensitive_ascii, Cache, DocEntry, DocSource, Errors,
-    Lowercase,
+    abbreviation_score_insensitive_ascii, full_text_match, fuzzy_score_insensitive_ascii,
+    highlight::Highlighter, index::SearchIndex, match_score_insensitive_ascii,
+    prefix_score_insensitive_ascii, query::Query, regex_score, signature::Signature,
+    starts_with_insensitive_ascii, top_results, Cache, DocEntry, DocSource, Errors, Filterable,
+    Glob, Lowercase, Match, SearchResult, SCORE_PREFIX, SCORE_SEGMENT, SOURCE_HOME_MANAGER_OPTIONS,
+    SOURCE_NIXOS_OPTIONS,
 };
 use colored::*;
+use regex::bytes::Regex;
 use serde::{Deserialize, Serialize};
-use std::{
-    collections::HashMap,
-    fs::File,
-    io::BufReader,
-    path::{Path, PathBuf},
-    process::Command,
-};
+use std::{borrow::Cow, collections::BTreeMap, ops::Range, path::PathBuf, process::Command};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct OptionDocumentation {
@@ -27,18 +26,148 @@ pub struct OptionDocumentation {
     option_type: String,
 }
 
+impl Filterable for OptionDocumentation {
+    fn filter_field(&self, field: &str) -> Option<Cow<'_, str>> {
+        match field {
+            "type" => Some(Cow::from(&self.option_type)),
+            "readonly" => Some(Cow::from(self.read_only.to_string())),
+            _ => None,
+        }
+    }
+}
+
 impl OptionDocumentation {
     pub fn name(&self) -> String {
         self.location.join(".")
     }
-    pub fn pretty_printed(&self) -> String {
+    pub fn pretty_printed(&self, highlighter: &Highlighter) -> String {
         format!(
             "# {}\n{}\ntype: {}\n\n",
-            self.name().blue().bold(),
-            self.description,
+            highlighter.name(&self.name(), |s| s.blue().bold()),
+            highlighter.text(&self.description, |s| s.normal()),
             self.option_type
         )
     }
+    /// Fields searched in full-text mode, besides the name
+    pub fn text_fields(&self) -> Vec<(&'static str, &str)> {
+        vec![("description", &self.description)]
+    }
+    /// Whether the location has placeholders for the names of submodules, like
+    /// `services.nginx.virtualHosts.<name>.root`
+    fn has_placeholders(&self) -> bool {
+        self.location.iter().any(|s| is_placeholder(s))
+    }
+    /// The lowercase segments before the first placeholder, like `services.nginx.virtualhosts`
+    /// for `services.nginx.virtualHosts.<name>.root`. A path has to start with them to fill
+    /// the placeholders.
+    fn fixed_prefix(&self) -> String {
+        self.location
+            .iter()
+            .take_while(|s| !is_placeholder(s))
+            .map(|s| s.to_ascii_lowercase())
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+    /// Scores a concrete path from a configuration, like
+    /// `services.nginx.virtualHosts."example.com".root`, if it resolves to this option by
+    /// filling its placeholders, or to one of its parents
+    fn placeholder_score(&self, path: &Lowercase) -> Option<Match> {
+        if !self.has_placeholders() {
+            return None;
+        }
+        let path = path_segments(path.0);
+        if let Some(ranges) = matches_location(&self.location, &path, false) {
+            Some(Match::new(SCORE_PREFIX, ranges))
+        } else {
+            matches_location(&self.location, &path, true)
+                .map(|ranges| Match::new(SCORE_SEGMENT, ranges))
+        }
+    }
+}
+
+fn is_placeholder(segment: &str) -> bool {
+    segment == "*" || (segment.starts_with('<') && segment.ends_with('>'))
+}
+
+/// Splits a dotted attribute path into its segments, quoted segments can contain dots
+fn path_segments(path: &[u8]) -> Vec<&[u8]> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, b) in path.iter().enumerate() {
+        match b {
+            b'"' => quoted = !quoted,
+            b'.' if !quoted => {
+                segments.push(&path[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    segments.push(&path[start..]);
+    segments
+        .into_iter()
+        .map(|s| {
+            s.strip_prefix(b"\"")
+                .and_then(|s| s.strip_suffix(b"\""))
+                .unwrap_or(s)
+        })
+        .collect()
+}
+
+/// Matches the lowercase `path` against `location`, placeholder segments match one or more
+/// of its segments. With `prefix`, it only has to match the start of `location`, and its
+/// last segment can be the start of a segment.
+///
+/// Returns the ranges of the location joined with dots that the path matched.
+fn matches_location(
+    location: &[String],
+    path: &[&[u8]],
+    prefix: bool,
+) -> Option<Vec<Range<usize>>> {
+    matches_location_at(location, path, prefix, 0)
+}
+
+/// `matches_location`, for a `location` starting at `offset` in the whole one
+fn matches_location_at(
+    location: &[String],
+    path: &[&[u8]],
+    prefix: bool,
+    offset: usize,
+) -> Option<Vec<Range<usize>>> {
+    let (segment, location_rest) = match location.split_first() {
+        Some(split) => split,
+        None => return path.is_empty().then(Vec::new),
+    };
+    let (first, path_rest) = match path.split_first() {
+        Some(split) => split,
+        None => return prefix.then(Vec::new),
+    };
+    let rest_offset = offset + segment.len() + 1;
+
+    let (range, rest) = if is_placeholder(segment) {
+        // names can contain dots, but a path ending inside a name is only taken as a prefix
+        // if the name is a single segment, or any path would match
+        let rest = (1..=path.len())
+            .filter(|n| *n == 1 || *n < path.len() || location_rest.is_empty())
+            .find_map(|n| matches_location_at(location_rest, &path[n..], prefix, rest_offset))?;
+        (offset..offset + segment.len(), rest)
+    } else {
+        let len = if prefix && path_rest.is_empty() {
+            starts_with_insensitive_ascii(segment.as_bytes(), &Lowercase(first))
+                .then_some(first.len())?
+        } else {
+            segment
+                .as_bytes()
+                .eq_ignore_ascii_case(first)
+                .then_some(segment.len())?
+        };
+        let rest = matches_location_at(location_rest, path_rest, prefix, rest_offset)?;
+        (offset..offset + len, rest)
+    };
+    let mut ranges = vec![range];
+    ranges.extend(rest);
+    Some(ranges)
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -47,45 +176,223 @@ pub enum OptionsDatabaseType {
     HomeManager,
 }
 
+impl OptionsDatabaseType {
+    /// Name of the source of these options, as returned by `DocEntry::source`
+    pub fn source(&self) -> &'static str {
+        match self {
+            OptionsDatabaseType::NixOS => SOURCE_NIXOS_OPTIONS,
+            OptionsDatabaseType::HomeManager => SOURCE_HOME_MANAGER_OPTIONS,
+        }
+    }
+}
+
+/// An option and the kind of options it's from, which `source:` filters are checked against
+struct SourcedOption<'a>(OptionsDatabaseType, &'a OptionDocumentation);
+
+impl Filterable for SourcedOption<'_> {
+    fn filter_field(&self, field: &str) -> Option<Cow<'_, str>> {
+        match field {
+            "source" => Some(Cow::from(self.0.source())),
+            _ => self.1.filter_field(field),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OptionsDatabase {
     pub typ: OptionsDatabaseType,
-    pub options: HashMap<String, OptionDocumentation>,
+    pub options: BTreeMap<String, OptionDocumentation>,
+    index: SearchIndex<String>,
+    /// Options with placeholders in their location, which the index can't narrow down, by
+    /// their `fixed_prefix`
+    placeholder_keys: BTreeMap<String, Vec<String>>,
 }
 
 impl OptionsDatabase {
     pub fn new(typ: OptionsDatabaseType) -> Self {
         Self {
             typ,
-            options: HashMap::new(),
+            options: BTreeMap::new(),
+            index: SearchIndex::default(),
+            placeholder_keys: BTreeMap::new(),
+        }
+    }
+
+    fn build_index(&mut self) {
+        self.index = self
+            .options
+            .iter()
+            .map(|(key, d)| (key.clone(), key.as_str(), d.text_fields()))
+            .collect();
+        self.placeholder_keys = BTreeMap::new();
+        for (key, d) in self.options.iter().filter(|(_, d)| d.has_placeholders()) {
+            self.placeholder_keys
+                .entry(d.fixed_prefix())
+                .or_default()
+                .push(key.clone());
         }
     }
+
+    /// Options with placeholders that the lowercase `path` may fill, the ones whose
+    /// `fixed_prefix` is the start of `path`
+    fn placeholder_candidates<'a>(&'a self, path: &[u8]) -> impl Iterator<Item = &'a String> {
+        let segments = path_segments(path);
+        // a path ending before the placeholders matches the key itself, the index finds it
+        let prefixes: Vec<String> = (0..segments.len())
+            .map(|n| String::from_utf8_lossy(&segments[..n].join(&b'.')).into_owned())
+            .collect();
+        prefixes
+            .into_iter()
+            .filter_map(move |prefix| self.placeholder_keys.get(&prefix))
+            .flatten()
+    }
+
+    /// Candidates of the index, with the options that may match terms of `query` through
+    /// their placeholders
+    fn with_placeholders<'a>(
+        &'a self,
+        keys: Option<Vec<&'a String>>,
+        query: &Query,
+    ) -> Option<Vec<&'a String>> {
+        keys.map(|mut keys| {
+            for terms in query.alternatives() {
+                for term in terms.iter().filter(|t| !t.negated && t.field.is_none()) {
+                    keys.extend(self.placeholder_candidates(&term.text));
+                }
+            }
+            keys.sort_unstable();
+            keys.dedup();
+            keys
+        })
+    }
+
+    /// Options with the given keys, or all of them if the index couldn't narrow them down
+    fn candidates(&self, keys: Option<Vec<&String>>) -> Vec<(&String, &OptionDocumentation)> {
+        match keys {
+            Some(keys) => keys
+                .into_iter()
+                .filter_map(|k| self.options.get_key_value(k))
+                .collect(),
+            None => self.options.iter().collect(),
+        }
+    }
+
+    fn search_with(
+        &self,
+        limit: Option<usize>,
+        keys: Option<Vec<&String>>,
+        score: impl Fn(&[u8], &OptionDocumentation) -> Option<Match>,
+    ) -> Vec<SearchResult> {
+        let matches = self
+            .candidates(keys)
+            .into_iter()
+            .filter_map(|(key, d)| Some((score(key.as_bytes(), d)?, d)))
+            .collect();
+        top_results(matches, limit, |m, d| {
+            SearchResult::new(DocEntry::OptionDoc(self.typ, d.clone()), m.score)
+                .with_name_ranges(m.ranges)
+        })
+    }
 }
 
-pub fn try_from_file(path: &PathBuf) -> Result<HashMap<String, OptionDocumentation>, Errors> {
-    let options: HashMap<String, OptionDocumentation> =
+pub fn try_from_file(path: &PathBuf) -> Result<BTreeMap<String, OptionDocumentation>, Errors> {
+    let options: BTreeMap<String, OptionDocumentation> =
         serde_json::from_slice(&std::fs::read(path)?)?;
     Ok(options)
 }
 
 impl DocSource for OptionsDatabase {
+    fn sources(&self) -> Vec<&str> {
+        vec![self.typ.source()]
+    }
     fn all_keys(&self) -> Vec<&str> {
         self.options.keys().map(|x| x.as_ref()).collect()
     }
-    fn search(&self, query: &Lowercase) -> Vec<DocEntry> {
-        self.options
-            .iter()
-            .filter(|(key, _)| starts_with_insensitive_ascii(key.as_bytes(), query))
-            .map(|(_, d)| DocEntry::OptionDoc(self.typ, d.clone()))
-            .collect()
+    fn search(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        let candidates = self.with_placeholders(self.index.prefix_candidates(query), query);
+        self.search_with(limit, candidates, |key, d| {
+            query.score(key, &SourcedOption(self.typ, d), |key, term| {
+                prefix_score_insensitive_ascii(key, term).or_else(|| d.placeholder_score(term))
+            })
+        })
     }
-    fn search_liberal(&self, query: &Lowercase) -> Vec<DocEntry> {
-        self.options
-            .iter()
-            .filter(|(key, _)| contains_insensitive_ascii(key.as_bytes(), query))
-            .map(|(_, d)| DocEntry::OptionDoc(self.typ, d.clone()))
+    fn search_liberal(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        let candidates = self.with_placeholders(self.index.key_candidates(query), query);
+        self.search_with(limit, candidates, |key, d| {
+            query.score(key, &SourcedOption(self.typ, d), |key, term| {
+                match_score_insensitive_ascii(key, term).or_else(|| d.placeholder_score(term))
+            })
+        })
+    }
+    fn search_fuzzy(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |key, d| {
+            query.score(
+                key,
+                &SourcedOption(self.typ, d),
+                fuzzy_score_insensitive_ascii,
+            )
+        })
+    }
+    fn search_abbreviation(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |key, d| {
+            query.score(
+                key,
+                &SourcedOption(self.typ, d),
+                abbreviation_score_insensitive_ascii,
+            )
+        })
+    }
+    fn search_regex(&self, regex: &Regex, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |key, _| regex_score(key, regex))
+    }
+    fn search_glob(&self, glob: &Glob, limit: Option<usize>) -> Vec<SearchResult> {
+        // matched against the location, as option names can contain dots themselves
+        let candidates = self.index.literal_candidates(glob.longest_literal());
+        self.search_with(limit, candidates, |_, d| {
+            let segments: Vec<&[u8]> = d.location.iter().map(|s| s.as_bytes()).collect();
+            glob.score_segments(&segments)
+        })
+    }
+    fn lookup(&self, name: &str) -> Vec<SearchResult> {
+        if let Some(d) = self.options.get(name) {
+            return vec![SearchResult::exact(DocEntry::OptionDoc(
+                self.typ,
+                d.clone(),
+            ))];
+        }
+        // a concrete path from a configuration, resolved to the declaration with placeholders
+        let path = path_segments(name.as_bytes());
+        self.placeholder_candidates(name.to_ascii_lowercase().as_bytes())
+            .map(|key| &self.options[key])
+            .filter_map(|d| {
+                let ranges = matches_location(&d.location, &path, false)?;
+                let entry = DocEntry::OptionDoc(self.typ, d.clone());
+                Some(SearchResult::new(entry, SCORE_PREFIX).with_name_ranges(ranges))
+            })
             .collect()
     }
+    fn search_type(&self, _: &Signature, _: Option<usize>) -> Vec<SearchResult> {
+        // options have types, but aren't functions
+        Vec::new()
+    }
+    fn search_full_text(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        let matches = self
+            .candidates(self.index.full_text_candidates(query))
+            .into_iter()
+            .filter_map(|(key, d)| {
+                let sourced = SourcedOption(self.typ, d);
+                let (m, fields) =
+                    full_text_match(key.as_bytes(), &sourced, &d.text_fields(), query)?;
+                Some((m, (d, fields)))
+            })
+            .collect();
+        top_results(matches, limit, |m, (d, fields)| {
+            let entry = DocEntry::OptionDoc(self.typ, d.clone());
+            SearchResult::new(entry, m.score)
+                .with_matched_fields(fields)
+                .with_name_ranges(m.ranges)
+        })
+    }
     fn update(&mut self) -> Result<bool, Errors> {
         let opts = match self.typ {
             OptionsDatabaseType::NixOS => try_from_file(&get_nixos_json_doc_path()?)?,
@@ -93,6 +400,7 @@ impl DocSource for OptionsDatabase {
         };
 
         let old = std::mem::replace(&mut self.options, opts);
+        self.build_index();
 
         Ok(old.keys().eq(self.options.keys()))
     }
@@ -130,3 +438,197 @@ pub fn get_nixos_json_doc_path() -> Result<PathBuf, std::io::Error> {
 
     Ok(PathBuf::from(base_path_output.trim_end_matches("\n")))
 }
+
+#[test]
+fn test_placeholder_score() {
+    let option = |location: &str| OptionDocumentation {
+        description: String::new(),
+        read_only: false,
+        location: location.split('.').map(str::to_owned).collect(),
+        option_type: String::new(),
+    };
+    let placeholder_match = |location: &str, path: &str| {
+        option(location).placeholder_score(&Lowercase(path.to_ascii_lowercase().as_bytes()))
+    };
+    let score = |location: &str, path: &str| placeholder_match(location, path).map(|m| m.score);
+    let root = "services.nginx.virtualHosts.<name>.root";
+
+    assert_eq!(
+        score(root, "services.nginx.virtualHosts.example.root"),
+        Some(SCORE_PREFIX)
+    );
+    assert_eq!(
+        score(root, "services.nginx.virtualHosts.example.com.root"),
+        Some(SCORE_PREFIX)
+    );
+    assert_eq!(
+        score(root, r#"services.nginx.virtualHosts."example.com".root"#),
+        Some(SCORE_PREFIX)
+    );
+    assert_eq!(
+        score(root, "services.nginx.virtualHosts.example.ro"),
+        Some(SCORE_SEGMENT)
+    );
+    assert_eq!(
+        score(root, "services.nginx.virtualHosts.example"),
+        Some(SCORE_SEGMENT)
+    );
+    // `root` could be the name of a virtual host
+    assert_eq!(
+        score(root, "services.nginx.virtualHosts.root"),
+        Some(SCORE_SEGMENT)
+    );
+    assert_eq!(
+        score(root, "services.nginx.virtualHosts.example.root.x"),
+        None
+    );
+    assert_eq!(
+        score(root, "services.caddy.virtualHosts.example.root"),
+        None
+    );
+    assert_eq!(
+        score(
+            "users.users.<name?>.extraGroups",
+            "users.users.alice.extraGroups"
+        ),
+        Some(SCORE_PREFIX)
+    );
+    assert_eq!(
+        score("fileSystems.*.options", r#"fileSystems."/boot".options"#),
+        Some(SCORE_PREFIX)
+    );
+    assert_eq!(
+        score("services.nginx.enable", "services.nginx.enable"),
+        None
+    );
+
+    assert_eq!(
+        placeholder_match(root, "services.nginx.virtualHosts.example.com.root"),
+        Some(Match::new(
+            SCORE_PREFIX,
+            vec![0..8, 9..14, 15..27, 28..34, 35..39]
+        ))
+    );
+    assert_eq!(
+        placeholder_match(root, "services.nginx.virtualHosts.example.ro"),
+        Some(Match::new(
+            SCORE_SEGMENT,
+            vec![0..8, 9..14, 15..27, 28..34, 35..37]
+        ))
+    );
+    assert_eq!(
+        placeholder_match(root, "services.nginx.virtualHosts.example"),
+        Some(Match::new(SCORE_SEGMENT, vec![0..8, 9..14, 15..27, 28..34]))
+    );
+}
+
+#[test]
+fn test_source_aliases() {
+    let option = OptionDocumentation {
+        description: String::new(),
+        read_only: false,
+        location: vec!["programs".to_owned(), "git".to_owned()],
+        option_type: String::new(),
+    };
+    let hm = DocEntry::OptionDoc(OptionsDatabaseType::HomeManager, option.clone());
+    let nixos = DocEntry::OptionDoc(OptionsDatabaseType::NixOS, option.clone());
+    let includes =
+        |query: &str, entry: &DocEntry| Query::parse(query).includes_source(entry.source());
+
+    assert!(includes("@hm git", &hm));
+    assert!(includes("@hm_options git", &hm));
+    assert!(includes("@options git", &hm));
+    assert!(!includes("@hm git", &nixos));
+    assert!(includes("@options git", &nixos));
+    assert!(!includes("@nixos git", &hm));
+
+    let mut database = OptionsDatabase::new(OptionsDatabaseType::HomeManager);
+    database.options.insert("programs.git".to_owned(), option);
+    database.build_index();
+    assert_eq!(
+        database
+            .search(&Query::parse("source:hm programs"), None)
+            .len(),
+        1
+    );
+    assert!(database
+        .search(&Query::parse("source:nixos programs"), None)
+        .is_empty());
+}
+
+#[test]
+fn test_lookup() {
+    let mut database = OptionsDatabase::new(OptionsDatabaseType::NixOS);
+    for location in [
+        "services.nginx.enable",
+        "services.nginx.virtualHosts.<name>.root",
+    ] {
+        let option = OptionDocumentation {
+            description: String::new(),
+            read_only: false,
+            location: location.split('.').map(str::to_owned).collect(),
+            option_type: String::new(),
+        };
+        database.options.insert(location.to_owned(), option);
+    }
+    database.build_index();
+    let lookup = |name: &str| -> Vec<(String, u32)> {
+        database
+            .lookup(name)
+            .into_iter()
+            .map(|result| (result.entry.name(), result.score))
+            .collect()
+    };
+
+    let exact = lookup("services.nginx.enable");
+    assert_eq!(exact[0].0, "services.nginx.enable");
+    // a concrete path resolves to its declaration, ranked below exact names
+    let resolved = lookup("services.nginx.virtualHosts.example.com.root");
+    assert_eq!(resolved[0].0, "services.nginx.virtualHosts.<name>.root");
+    assert!(resolved[0].1 < exact[0].1);
+    assert_eq!((exact.len(), resolved.len()), (1, 1));
+    assert!(lookup("services.nginx").is_empty());
+    assert!(lookup("services.nginx.virtualHosts.example.com.index").is_empty());
+}
+
+#[test]
+fn test_placeholder_candidates() {
+    let mut database = OptionsDatabase::new(OptionsDatabaseType::NixOS);
+    for location in [
+        "services.nginx.virtualHosts.<name>.root",
+        "users.users.<name>.home",
+        "services.nginx.enable",
+    ] {
+        let option = OptionDocumentation {
+            description: String::new(),
+            read_only: false,
+            location: location.split('.').map(str::to_owned).collect(),
+            option_type: String::new(),
+        };
+        database.options.insert(location.to_owned(), option);
+    }
+    database.build_index();
+    let candidates =
+        |path: &str| -> Vec<&String> { database.placeholder_candidates(path.as_bytes()).collect() };
+
+    assert_eq!(
+        candidates("services.nginx.virtualhosts.example.com.root"),
+        vec!["services.nginx.virtualHosts.<name>.root"]
+    );
+    assert_eq!(
+        candidates("users.users.alice"),
+        vec!["users.users.<name>.home"]
+    );
+    assert!(candidates("services.nginx").is_empty());
+    assert!(candidates("nginx.virtualhosts.example").is_empty());
+
+    let found: Vec<String> = database
+        .search_liberal(
+            &Query::parse("services.nginx.virtualHosts.example.root"),
+            None,
+        )
+        .into_iter()
+        .map(|result| result.entry.name())
+        .collect();
+    assert_eq!(found, vec!["services.nginx.virtualHosts.<name>.root"]);
+}
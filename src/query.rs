@@ -0,0 +1,439 @@
+use crate::{
+    contains_insensitive_ascii, Filterable, Lowercase, Match, Normalization,
+    SOURCE_HOME_MANAGER_OPTIONS, SOURCE_NIXOS_OPTIONS, SOURCE_NIXPKGS_COMMENTS,
+    SOURCE_NIXPKGS_DOCUMENTATION, SOURCE_NIXPKGS_TREE,
+};
+use std::borrow::Cow;
+
+/// A parsed search query.
+///
+/// Whitespace separated terms all have to match, `OR` separates alternatives, a term
+/// starting with `-` excludes entries it matches, and `"quoted phrases"` are kept as one term.
+/// For example `nginx ssl OR caddy -tls` matches entries that have both `nginx` and `ssl`
+/// in them, or `caddy` but not `tls`.
+///
+/// Terms like `type:port` or `path:"pkgs/build-support"` are filters, they're checked against
+/// a field of the entry instead of its name, see `FILTER_FIELDS`. `source:` filters match the
+/// name of the source, or take the aliases of `SOURCE_ALIASES`, like `source:hm`.
+///
+/// Terms like `@hm` restrict the search to some sources, see `SOURCE_ALIASES`. They apply to
+/// the whole query wherever they are, and several of them add up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    /// Alternatives joined by `OR`, each a list of terms that all have to match
+    alternatives: Vec<Vec<Term>>,
+    normalization: Normalization,
+    /// Sources picked with `@alias` terms, as named by `DocEntry::source`. Empty for all of them
+    sources: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+    /// Case folded text of the term
+    pub text: Vec<u8>,
+    pub negated: bool,
+    /// Field this term filters on, matched against the name if None
+    pub field: Option<String>,
+}
+
+/// Fields that can be used in `field:value` filters
+pub const FILTER_FIELDS: &[&str] = &["type", "path", "readonly", "source"];
+
+/// Aliases that can be used in `@alias` terms, and the sources they stand for
+pub const SOURCE_ALIASES: &[(&str, &[&str])] = &[
+    ("nixos", &[SOURCE_NIXOS_OPTIONS]),
+    ("nixos_options", &[SOURCE_NIXOS_OPTIONS]),
+    ("hm", &[SOURCE_HOME_MANAGER_OPTIONS]),
+    ("hm_options", &[SOURCE_HOME_MANAGER_OPTIONS]),
+    (
+        "options",
+        &[SOURCE_NIXOS_OPTIONS, SOURCE_HOME_MANAGER_OPTIONS],
+    ),
+    ("doc", &[SOURCE_NIXPKGS_DOCUMENTATION]),
+    ("nixpkgs_doc", &[SOURCE_NIXPKGS_DOCUMENTATION]),
+    ("tree", &[SOURCE_NIXPKGS_TREE]),
+    ("nixpkgs_tree", &[SOURCE_NIXPKGS_TREE]),
+    ("comments", &[SOURCE_NIXPKGS_COMMENTS]),
+    ("nixpkgs_comments", &[SOURCE_NIXPKGS_COMMENTS]),
+    (
+        "lib",
+        &[SOURCE_NIXPKGS_DOCUMENTATION, SOURCE_NIXPKGS_COMMENTS],
+    ),
+];
+
+fn source_alias(alias: &str) -> Option<&'static [&'static str]> {
+    SOURCE_ALIASES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(alias))
+        .map(|(_, sources)| *sources)
+}
+
+impl Term {
+    pub fn lowercase(&self) -> Lowercase<'_> {
+        Lowercase(&self.text)
+    }
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Self {
+        Self::parse_normalized(query, Normalization::default())
+    }
+
+    /// Parses the query, its terms and everything they're compared with are normalized
+    /// with `normalization`
+    pub fn parse_normalized(query: &str, normalization: Normalization) -> Self {
+        let mut alternatives = vec![Vec::new()];
+        let mut sources = Vec::new();
+        let mut chars = query.chars().peekable();
+
+        loop {
+            while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                chars.next();
+            }
+            let negated = match chars.peek() {
+                None => break,
+                Some('-') => {
+                    chars.next();
+                    true
+                }
+                Some(_) => false,
+            };
+
+            let mut text = String::new();
+            let mut field = None;
+            let mut quoted = false;
+            loop {
+                match chars.peek() {
+                    Some('"') if text.is_empty() => {
+                        chars.next();
+                        text.extend(chars.by_ref().take_while(|c| *c != '"'));
+                        quoted = true;
+                        break;
+                    }
+                    Some(':') if field.is_none() && FILTER_FIELDS.contains(&text.as_str()) => {
+                        chars.next();
+                        field = Some(std::mem::take(&mut text));
+                    }
+                    Some(c) if !c.is_whitespace() => {
+                        text.push(*c);
+                        chars.next();
+                    }
+                    _ => break,
+                }
+            }
+
+            let picked_sources = text.strip_prefix('@').and_then(source_alias);
+            if !quoted && !negated && field.is_none() && text == "OR" {
+                alternatives.push(Vec::new());
+            } else if let (Some(picked), false, false) = (picked_sources, quoted, negated) {
+                for source in picked {
+                    if !sources.contains(source) {
+                        sources.push(*source);
+                    }
+                }
+            } else if text.is_empty() && !quoted && field.is_none() {
+                // a lone `-`
+                alternatives.last_mut().unwrap().push(Term {
+                    text: b"-".to_vec(),
+                    negated: false,
+                    field,
+                });
+            } else {
+                alternatives.last_mut().unwrap().push(Term {
+                    text: normalization.lowercase(&text),
+                    negated,
+                    field,
+                });
+            }
+        }
+
+        if alternatives.len() > 1 {
+            alternatives.retain(|terms| !terms.is_empty());
+        }
+        if alternatives.is_empty() {
+            alternatives.push(Vec::new());
+        }
+
+        Self {
+            alternatives,
+            normalization,
+            sources,
+        }
+    }
+
+    pub fn alternatives(&self) -> &[Vec<Term>] {
+        &self.alternatives
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    /// Whether entries of `source`, as named by `DocEntry::source`, are searched
+    pub fn includes_source(&self, source: &str) -> bool {
+        self.sources.is_empty() || self.sources.contains(&source)
+    }
+
+    /// Scores `key` by applying `score_term` to every term, filters are checked against `entry`.
+    ///
+    /// An alternative matches if all of its terms match and none of the negated ones do,
+    /// it's scored with the average of its terms' scores. An alternative with no positive
+    /// terms other than filters is scored like an empty query. The best scoring alternative
+    /// is used, with the ranges of `key` its positive terms matched.
+    pub(crate) fn score(
+        &self,
+        key: &[u8],
+        entry: &dyn Filterable,
+        score_term: impl Fn(&[u8], &Lowercase) -> Option<Match>,
+    ) -> Option<Match> {
+        self.score_alternative(key, entry, score_term)
+            .map(|(m, _)| m)
+    }
+
+    /// Like `score`, also returns the terms of the alternative that was used
+    pub(crate) fn score_alternative(
+        &self,
+        key: &[u8],
+        entry: &dyn Filterable,
+        score_term: impl Fn(&[u8], &Lowercase) -> Option<Match>,
+    ) -> Option<(Match, &[Term])> {
+        // the ranges are matched in the folded key, and mapped back to `key`
+        let (folded, offsets) = if key.is_ascii() {
+            (Cow::Borrowed(key), None)
+        } else {
+            let (folded, offsets) = self
+                .normalization
+                .fold_with_offsets(&String::from_utf8_lossy(key));
+            (Cow::Owned(folded), Some(offsets))
+        };
+        let key = folded.as_ref();
+        let m = self
+            .alternatives
+            .iter()
+            .filter_map(|terms| {
+                let mut total = 0;
+                let mut count = 0;
+                let mut ranges = Vec::new();
+                for term in terms {
+                    if let Some(field) = &term.field {
+                        let matches = entry.filter_field(field).is_some_and(|value| {
+                            let is_alias = field == "source"
+                                && std::str::from_utf8(&term.text)
+                                    .ok()
+                                    .and_then(source_alias)
+                                    .is_some_and(|sources| sources.contains(&value.as_ref()));
+                            let value = self.normalization.fold(value.as_bytes());
+                            is_alias || contains_insensitive_ascii(&value, &term.lowercase())
+                        });
+                        if matches == term.negated {
+                            return None;
+                        }
+                        continue;
+                    }
+                    match (score_term(key, &term.lowercase()), term.negated) {
+                        (Some(_), true) | (None, false) => return None,
+                        (Some(m), false) => {
+                            total += m.score;
+                            count += 1;
+                            ranges.extend(m.ranges);
+                        }
+                        (None, true) => (),
+                    }
+                }
+                let m = match total.checked_div(count) {
+                    Some(average) => Match::new(average, ranges),
+                    None => score_term(key, &Lowercase(b""))?,
+                };
+                Some((m, terms.as_slice()))
+            })
+            .max_by_key(|(m, _)| m.score)?;
+
+        Some(match offsets {
+            Some(offsets) => {
+                let ranges =
+                    m.0.ranges
+                        .iter()
+                        .map(|range| offsets[range.start].start..offsets[range.end - 1].end)
+                        .collect();
+                (Match::new(m.0.score, ranges), m.1)
+            }
+            None => m,
+        })
+    }
+}
+
+#[cfg(test)]
+fn term(text: &str, negated: bool) -> Term {
+    Term {
+        text: text.as_bytes().to_vec(),
+        negated,
+        field: None,
+    }
+}
+
+#[cfg(test)]
+fn filter(field: &str, text: &str, negated: bool) -> Term {
+    Term {
+        field: Some(field.to_owned()),
+        ..term(text, negated)
+    }
+}
+
+#[cfg(test)]
+impl Filterable for Vec<(&str, &str)> {
+    fn filter_field(&self, field: &str) -> Option<std::borrow::Cow<'_, str>> {
+        self.iter()
+            .find(|(name, _)| *name == field)
+            .map(|(_, value)| (*value).into())
+    }
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(Query::parse("").alternatives(), &[vec![]]);
+    assert_eq!(
+        Query::parse("  nginx   SSL ").alternatives(),
+        &[vec![term("nginx", false), term("ssl", false)]]
+    );
+    assert_eq!(
+        Query::parse("nginx ssl OR caddy -tls").alternatives(),
+        &[
+            vec![term("nginx", false), term("ssl", false)],
+            vec![term("caddy", false), term("tls", true)]
+        ]
+    );
+    assert_eq!(
+        Query::parse(r#""open the firewall" -"udp ports" or"#).alternatives(),
+        &[vec![
+            term("open the firewall", false),
+            term("udp ports", true),
+            term("or", false)
+        ]]
+    );
+    assert_eq!(
+        Query::parse("OR home-manager - \"OR\"").alternatives(),
+        &[vec![
+            term("home-manager", false),
+            term("-", false),
+            term("or", false)
+        ]]
+    );
+    assert_eq!(
+        Query::parse(r#"type:Port services. -readonly:true path:"pkgs/build support" foo:bar"#)
+            .alternatives(),
+        &[vec![
+            filter("type", "port", false),
+            term("services.", false),
+            filter("readonly", "true", true),
+            filter("path", "pkgs/build support", false),
+            term("foo:bar", false),
+        ]]
+    );
+}
+
+#[test]
+fn test_sources() {
+    let query = Query::parse("@hm programs.git");
+    assert_eq!(query.alternatives(), &[vec![term("programs.git", false)]]);
+    assert!(query.includes_source(SOURCE_HOME_MANAGER_OPTIONS));
+    assert!(!query.includes_source(SOURCE_NIXOS_OPTIONS));
+
+    let query = Query::parse("concat @LIB OR @tree -@hm \"@nixos\" @unknown");
+    assert_eq!(
+        query.alternatives(),
+        &[
+            vec![term("concat", false)],
+            vec![
+                term("@hm", true),
+                term("@nixos", false),
+                term("@unknown", false)
+            ]
+        ]
+    );
+    assert!(query.includes_source(SOURCE_NIXPKGS_DOCUMENTATION));
+    assert!(query.includes_source(SOURCE_NIXPKGS_COMMENTS));
+    assert!(query.includes_source(SOURCE_NIXPKGS_TREE));
+    assert!(!query.includes_source(SOURCE_HOME_MANAGER_OPTIONS));
+
+    assert!(Query::parse("programs.git").includes_source(SOURCE_NIXOS_OPTIONS));
+}
+
+#[test]
+fn test_source_filter() {
+    let hm = vec![("source", SOURCE_HOME_MANAGER_OPTIONS)];
+    let nixos = vec![("source", SOURCE_NIXOS_OPTIONS)];
+    let score = |query: &str, entry: &Vec<(&str, &str)>| {
+        Query::parse(query)
+            .score(b"programs.git.enable", entry, |_, _| {
+                Some(Match::new(1, vec![]))
+            })
+            .map(|m| m.score)
+    };
+
+    assert_eq!(score("source:hm git", &hm), Some(1));
+    assert_eq!(score("source:hm git", &nixos), None);
+    assert_eq!(score("source:homemanager git", &hm), Some(1));
+    assert_eq!(score("source:options git", &nixos), Some(1));
+    assert_eq!(score("-source:nixos git", &nixos), None);
+    assert_eq!(
+        score("source:hm git OR source:nixos enable", &nixos),
+        Some(1)
+    );
+}
+
+#[test]
+fn test_score() {
+    let contains = |s: &[u8], term: &Lowercase| {
+        crate::find_insensitive_ascii(s, term)
+            .map(|range| Match::new(term.0.len() as u32, vec![range]))
+    };
+    let fields = vec![("type", "boolean"), ("readonly", "false")];
+    let matches =
+        |query: &str, key: &str| Query::parse(query).score(key.as_bytes(), &fields, contains);
+    let score = |query: &str, key: &str| matches(query, key).map(|m| m.score);
+
+    assert_eq!(score("", "services.nginx.enable"), Some(0));
+    assert_eq!(score("nginx enable", "services.nginx.enable"), Some(5));
+    assert_eq!(score("nginx ssl", "services.nginx.enable"), None);
+    assert_eq!(
+        score("nginx ssl OR enable", "services.nginx.enable"),
+        Some(6)
+    );
+    assert_eq!(score("nginx -enable", "services.nginx.enable"), None);
+    assert_eq!(score("-caddy", "services.nginx.enable"), Some(0));
+    assert_eq!(score("type:bool nginx", "services.nginx.enable"), Some(5));
+    assert_eq!(score("type:bool", "services.nginx.enable"), Some(0));
+    assert_eq!(score("type:str nginx", "services.nginx.enable"), None);
+    assert_eq!(score("readonly:true", "services.nginx.enable"), None);
+    assert_eq!(score("-readonly:true", "services.nginx.enable"), Some(0));
+    assert_eq!(score("path:pkgs", "services.nginx.enable"), None);
+    assert_eq!(score("-path:pkgs", "services.nginx.enable"), Some(0));
+
+    assert_eq!(
+        matches("nginx ssl OR enable -caddy", "services.nginx.enable"),
+        Some(Match::range(6, 15..21))
+    );
+    assert_eq!(
+        matches("ena nginx", "services.nginx.enable").map(|m| m.ranges),
+        Some(vec![9..14, 15..18])
+    );
+
+    assert_eq!(score("ÉCOLE", "programs.école.enable"), Some(6));
+    assert_eq!(
+        matches("ÉCOLE", "programs.école.enable"),
+        Some(Match::range(6, 9..15))
+    );
+    assert_eq!(score("straße", "STRASSE"), Some(7));
+    assert_eq!(score("ecole", "programs.école.enable"), None);
+    let strip_accents = Normalization {
+        strip_accents: true,
+    };
+    assert_eq!(
+        Query::parse_normalized("ecole", strip_accents).score(
+            "programs.École.enable".as_bytes(),
+            &fields,
+            contains
+        ),
+        Some(Match::range(5, 9..15))
+    );
+}
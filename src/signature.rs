@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+
+/// A parsed function type signature, like `concatStringsSep :: string -> [string] -> string`.
+///
+/// Curried arguments are flattened, so `a -> b -> c` is a function of `a` and `b` returning `c`.
+/// Single lowercase letters like `a` or `b1` are type variables, everything else is a concrete
+/// type, compared case-insensitively and with common aliases (`str`, `attrs`, `boolean`, ...)
+/// normalized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    args: Vec<Type>,
+    result: Type,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Type {
+    Var(String),
+    /// A named type, applied to arguments if any
+    Con(String, Vec<Type>),
+    List(Box<Type>),
+    Fun(Vec<Type>, Box<Type>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Arrow,
+    Open(char),
+    Close(char),
+    Ident(String),
+}
+
+fn tokenize(s: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '-' if chars.peek() == Some(&'>') => {
+                chars.next();
+                tokens.push(Token::Arrow);
+            }
+            '(' | '[' => tokens.push(Token::Open(c)),
+            ')' | ']' => tokens.push(Token::Close(c)),
+            // attribute sets are compared as a whole, their fields are skipped
+            '{' => {
+                let mut depth = 1;
+                for c in chars.by_ref() {
+                    match c {
+                        '{' => depth += 1,
+                        '}' if depth == 1 => break,
+                        '}' => depth -= 1,
+                        _ => (),
+                    }
+                }
+                tokens.push(Token::Ident("attrset".to_owned()));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' || c == '\'' || c == '.' {
+                        ident.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            _ => (),
+        }
+    }
+    tokens
+}
+
+/// Strips the `name ::` prefix of a signature, if any
+fn strip_name(s: &str) -> &str {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ':' if depth == 0 && s[i + 1..].starts_with(':') => return &s[i + 2..],
+            _ => (),
+        }
+    }
+    s
+}
+
+fn is_type_variable(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_lowercase())
+        && chars.all(|c| c.is_ascii_digit() || c == '\'')
+}
+
+fn normalize_name(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.as_str() {
+        "str" | "string" => "string",
+        "attrs" | "attrset" | "set" | "attributeset" => "attrset",
+        "bool" | "boolean" => "bool",
+        "int" | "integer" => "int",
+        "list" => "list",
+        _ => return name,
+    }
+    .to_owned()
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn function(&mut self) -> Option<Type> {
+        let mut types = vec![self.application()?];
+        while self.peek() == Some(&Token::Arrow) {
+            self.next();
+            types.push(self.application()?);
+        }
+        let result = types.pop()?;
+        if types.is_empty() {
+            Some(result)
+        } else {
+            Some(Type::Fun(types, Box::new(result)))
+        }
+    }
+
+    fn application(&mut self) -> Option<Type> {
+        let head = self.atom()?;
+        let mut args = Vec::new();
+        while let Some(Token::Ident(_)) | Some(Token::Open(_)) = self.peek() {
+            args.push(self.atom()?);
+        }
+        match head {
+            _ if args.is_empty() => Some(head),
+            Type::Con(name, _) => Some(Type::Con(name, args)),
+            // applied type variables are rare, treat them like unknown types
+            Type::Var(name) => Some(Type::Con(name, args)),
+            _ => None,
+        }
+    }
+
+    fn atom(&mut self) -> Option<Type> {
+        match self.next()? {
+            Token::Ident(name) if is_type_variable(&name) => Some(Type::Var(name)),
+            Token::Ident(name) => Some(Type::Con(normalize_name(&name), Vec::new())),
+            Token::Open('[') => {
+                let inner = self.function()?;
+                (self.next()? == Token::Close(']')).then(|| Type::List(Box::new(inner)))
+            }
+            Token::Open(_) if self.peek() == Some(&Token::Close(')')) => {
+                self.next();
+                Some(Type::Con("null".to_owned(), Vec::new()))
+            }
+            Token::Open(_) => {
+                let inner = self.function()?;
+                (self.next()? == Token::Close(')')).then_some(inner)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Penalty for every argument of the function that isn't in the query
+const PENALTY_EXTRA_ARG: u32 = 1000;
+/// Penalty for arguments matched out of order
+const PENALTY_REORDERED: u32 = 500;
+/// Penalty for every type variable of the function that's instantiated with a type
+const PENALTY_INSTANTIATED: u32 = 250;
+const SCORE_SIGNATURE: u32 = 4000;
+
+/// Type variables bound while unifying a query with a signature
+#[derive(Debug, Clone, Default)]
+struct Bindings {
+    /// Query variables renamed to signature variables
+    renamed: HashMap<String, String>,
+    /// Signature variables instantiated with a type from the query
+    instantiated: HashMap<String, Type>,
+}
+
+impl Bindings {
+    /// Unifies the query type `q` with the signature type `s`
+    fn unify(&mut self, q: &Type, s: &Type) -> bool {
+        match (q, s) {
+            (Type::Var(q), Type::Var(s)) if !self.instantiated.contains_key(s) => {
+                match self.renamed.get(q) {
+                    Some(bound) => bound == s,
+                    None if self.renamed.values().any(|v| v == s) => false,
+                    None => {
+                        self.renamed.insert(q.clone(), s.clone());
+                        true
+                    }
+                }
+            }
+            (_, Type::Var(s)) => match self.instantiated.get(s) {
+                Some(bound) => bound == q,
+                None if self.renamed.values().any(|v| v == s) => false,
+                None => {
+                    self.instantiated.insert(s.clone(), q.clone());
+                    true
+                }
+            },
+            (Type::Con(q, q_args), Type::Con(s, s_args)) => {
+                q == s && q_args.len() == s_args.len() && self.unify_all(q_args, s_args)
+            }
+            (Type::List(q), Type::List(s)) => self.unify(q, s),
+            (Type::Fun(q_args, q_result), Type::Fun(s_args, s_result)) => {
+                self.unify_all(q_args, s_args) && self.unify(q_result, s_result)
+            }
+            _ => false,
+        }
+    }
+
+    fn unify_all(&mut self, q: &[Type], s: &[Type]) -> bool {
+        q.len() == s.len() && q.iter().zip(s).all(|(q, s)| self.unify(q, s))
+    }
+}
+
+impl Signature {
+    /// Parses a signature, with or without the `name ::` in front of it.
+    /// Returns None if it isn't a valid type.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(strip_name(s)),
+            position: 0,
+        };
+        let ty = parser.function()?;
+        if parser.peek().is_some() {
+            return None;
+        }
+        Some(match ty {
+            Type::Fun(args, result) => Signature {
+                args,
+                result: *result,
+            },
+            ty => Signature {
+                args: Vec::new(),
+                result: ty,
+            },
+        })
+    }
+
+    /// Scores how well `signature` matches this one, used as a query.
+    ///
+    /// Type variables of the query can be renamed, and those of the signature instantiated
+    /// with any type. The arguments can be in any order, and the signature can take arguments
+    /// the query doesn't mention. The closer the match, the higher the score.
+    pub fn score(&self, signature: &Signature) -> Option<u32> {
+        if self.args.len() > signature.args.len() {
+            return None;
+        }
+        let mut bindings = Bindings::default();
+        if !bindings.unify(&self.result, &signature.result) {
+            return None;
+        }
+        let mut used = vec![false; signature.args.len()];
+        let penalty = self.match_args(signature, &mut used, None, &bindings)?;
+        let extra = (signature.args.len() - self.args.len()) as u32;
+        let penalty = penalty + extra * PENALTY_EXTRA_ARG;
+        Some(SCORE_SIGNATURE.saturating_sub(penalty).max(1))
+    }
+
+    /// Matches the query's remaining arguments against the unused arguments of `signature`,
+    /// returning the lowest penalty for doing so. `last` is the position of the signature's
+    /// argument the previous one was matched with.
+    fn match_args(
+        &self,
+        signature: &Signature,
+        used: &mut [bool],
+        last: Option<usize>,
+        bindings: &Bindings,
+    ) -> Option<u32> {
+        let matched = used.iter().filter(|u| **u).count();
+        let arg = match self.args.get(matched) {
+            Some(arg) => arg,
+            None => return Some(bindings.instantiated.len() as u32 * PENALTY_INSTANTIATED),
+        };
+
+        let mut best = None;
+        for (i, candidate) in signature.args.iter().enumerate() {
+            let mut bindings = bindings.clone();
+            if used[i] || !bindings.unify(arg, candidate) {
+                continue;
+            }
+            used[i] = true;
+            let penalty = self.match_args(signature, used, Some(i), &bindings);
+            used[i] = false;
+            if let Some(mut penalty) = penalty {
+                if last.is_some_and(|last| last > i) {
+                    penalty += PENALTY_REORDERED;
+                }
+                best = Some(best.map_or(penalty, |best: u32| best.min(penalty)));
+            }
+        }
+        best
+    }
+}
+
+#[test]
+fn test_parse() {
+    let string = || Type::Con("string".to_owned(), Vec::new());
+    assert_eq!(
+        Signature::parse("concatStringsSep :: string -> [String] -> str"),
+        Some(Signature {
+            args: vec![string(), Type::List(Box::new(string()))],
+            result: string(),
+        })
+    );
+    assert_eq!(
+        Signature::parse("(a -> b) -> { x :: int; } -> Maybe b1"),
+        Some(Signature {
+            args: vec![
+                Type::Fun(
+                    vec![Type::Var("a".to_owned())],
+                    Box::new(Type::Var("b".to_owned()))
+                ),
+                Type::Con("attrset".to_owned(), Vec::new()),
+            ],
+            result: Type::Con("maybe".to_owned(), vec![Type::Var("b1".to_owned())]),
+        })
+    );
+    assert_eq!(Signature::parse("a -> [b"), None);
+    assert_eq!(Signature::parse("a -> -> b"), None);
+    assert_eq!(Signature::parse(""), None);
+}
+
+#[test]
+fn test_score() {
+    let score = |query: &str, signature: &str| {
+        Signature::parse(query)?.score(&Signature::parse(signature)?)
+    };
+    let concat_strings = "concatStrings :: [string] -> string";
+    let concat_strings_sep = "concatStringsSep :: string -> [string] -> string";
+    let concat_map_strings = "concatMapStrings :: (a -> string) -> [a] -> string";
+
+    assert_eq!(score("[string] -> string", concat_strings), Some(4000));
+    assert_eq!(score("[string] -> string", concat_strings_sep), Some(3000));
+    assert_eq!(score("[string] -> string", concat_map_strings), Some(2750));
+    assert_eq!(score("string -> string", concat_strings), None);
+    assert_eq!(score("[str] -> str -> str", concat_strings_sep), Some(3500));
+    assert_eq!(
+        score("(x -> string) -> [x] -> string", concat_map_strings),
+        Some(4000)
+    );
+    assert_eq!(
+        score("(x -> string) -> [y] -> string", concat_map_strings),
+        None
+    );
+
+    let map = "map :: (a -> b) -> [a] -> [b]";
+    assert_eq!(score("(a -> a) -> [a] -> [a]", map), None);
+    assert_eq!(score("(int -> int) -> [int] -> [int]", map), Some(3500));
+    assert_eq!(score("[b] -> (b -> c) -> [c]", map), Some(3500));
+}
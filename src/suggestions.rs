@@ -0,0 +1,140 @@
+use crate::query::Query;
+use rayon::prelude::*;
+use std::cmp::Reverse;
+
+/// Keys with a similarity below this aren't suggested
+const MIN_SIMILARITY: u32 = 500;
+
+/// Keys closest to `query`, best first, to suggest when a search finds nothing.
+///
+/// Keys are ranked both by their edit distance to the query's terms, and by how close each
+/// dotted segment of a term is to a segment of the key, so `servics.ngnix` suggests
+/// `services.nginx.enable`. Only the terms matched against names count, not negated terms or
+/// filters, and keys are folded like the query. Duplicate keys are only suggested once.
+pub fn suggestions<'a>(keys: &[&'a str], query: &Query, count: usize) -> Vec<&'a str> {
+    let alternatives: Vec<Vec<&[u8]>> = query
+        .alternatives()
+        .iter()
+        .map(|terms| {
+            terms
+                .iter()
+                .filter(|term| !term.negated && term.field.is_none())
+                .map(|term| term.text.as_slice())
+                .collect::<Vec<_>>()
+        })
+        .filter(|terms| !terms.is_empty())
+        .collect();
+    let normalization = query.normalization();
+
+    let mut ranked: Vec<(u32, &str)> = keys
+        .par_iter()
+        .filter_map(|key| {
+            let folded = normalization.lowercase(key);
+            // the best alternative, with the average similarity of its terms
+            let score = alternatives
+                .iter()
+                .map(|terms| {
+                    let total: u32 = terms.iter().map(|term| similarity(&folded, term)).sum();
+                    total / terms.len() as u32
+                })
+                .max()?;
+            (score >= MIN_SIMILARITY).then_some((score, *key))
+        })
+        .collect();
+    ranked.par_sort_unstable_by_key(|(score, key)| (Reverse(*score), *key));
+    ranked.dedup_by_key(|(_, key)| *key);
+    ranked.into_iter().take(count).map(|(_, key)| key).collect()
+}
+
+/// Similarity of the folded `key` to a folded `term`, from 0 to 1000. The similarity of the
+/// segments weighs more than the whole key's, as the term is often only part of the key.
+fn similarity(key: &[u8], term: &[u8]) -> u32 {
+    let whole = normalized_similarity(key, term);
+
+    let term_segments: Vec<&[u8]> = term
+        .split(|b| *b == b'.')
+        .filter(|s| !s.is_empty())
+        .collect();
+    let segments = term_segments
+        .iter()
+        .map(|t| {
+            key.split(|b| *b == b'.')
+                .map(|k| normalized_similarity(k, t))
+                .max()
+                .unwrap_or(0)
+        })
+        .sum::<u32>()
+        .checked_div(term_segments.len() as u32)
+        .unwrap_or(0);
+
+    (whole + 2 * segments) / 3
+}
+
+/// 1000 for equal strings, down to 0 for strings with nothing in common
+fn normalized_similarity(a: &[u8], b: &[u8]) -> u32 {
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1000;
+    }
+    let distance = edit_distance(a, b);
+    (1000 * (longest - distance) / longest) as u32
+}
+
+/// Levenshtein distance between `a` and `b`
+fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substitution = diagonal + (x != y) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[test]
+fn test_edit_distance() {
+    assert_eq!(edit_distance(b"", b""), 0);
+    assert_eq!(edit_distance(b"abc", b""), 3);
+    assert_eq!(edit_distance(b"kitten", b"sitting"), 3);
+    assert_eq!(edit_distance(b"nginx", b"ngnix"), 2);
+}
+
+#[test]
+fn test_suggestions() {
+    let keys = [
+        "services.nginx.enable",
+        "services.nginx.package",
+        "services.caddy.enable",
+        "lib.mkOption",
+        "lib.mkOption",
+        "lib.types.mkOptionType",
+        "pkgs.hello",
+        "programs.École.enable",
+    ];
+    let suggestions = |query: &str, count| suggestions(&keys, &Query::parse(query), count);
+    assert_eq!(
+        suggestions("servics.ngnix.enable", 2),
+        vec!["services.nginx.enable", "services.caddy.enable"]
+    );
+    assert_eq!(suggestions("mkOptoin", 5), vec!["lib.mkOption"]);
+    assert_eq!(
+        suggestions("lib.mkOptionTyp", 5),
+        vec!["lib.types.mkOptionType", "lib.mkOption"]
+    );
+    assert_eq!(suggestions("zzzzzz", 5), Vec::<&str>::new());
+    // negated terms, filters and source aliases aren't part of any name
+    assert_eq!(
+        suggestions("mkOptoin -services type:string @lib", 5),
+        vec!["lib.mkOption"]
+    );
+    assert_eq!(suggestions("zzzzzz OR mkOptoin", 5), vec!["lib.mkOption"]);
+    assert_eq!(suggestions("-mkOption type:string", 5), Vec::<&str>::new());
+    assert_eq!(
+        suggestions("programs.ÉCOLE.enabl", 1),
+        vec!["programs.École.enable"]
+    );
+}
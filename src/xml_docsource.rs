@@ -2,20 +2,42 @@ use colored::*;
 use roxmltree::{self, Document};
 
 use crate::{
-    contains_insensitive_ascii, starts_with_insensitive_ascii, Cache, DocEntry, DocSource, Errors,
-    Lowercase,
+    abbreviation_score_insensitive_ascii, full_text_match, fuzzy_score_insensitive_ascii,
+    highlight::Highlighter, index::SearchIndex, match_score_insensitive_ascii,
+    prefix_score_insensitive_ascii, query::Query, regex_score, signature::Signature, top_results,
+    Cache, DocEntry, DocSource, Errors, Filterable, Glob, Match, SearchResult,
+    SOURCE_NIXPKGS_DOCUMENTATION,
 };
+use regex::bytes::Regex;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::PathBuf, process::Command};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+    path::PathBuf,
+    process::Command,
+};
 use walkdir::WalkDir;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct XmlFuncDocumentation {
-    name: String,
-    description: String,
-    fn_type: Option<String>,
-    args: Vec<(String, String)>,
-    example: Option<String>,
+    pub(crate) name: String,
+    pub(crate) description: String,
+    pub(crate) fn_type: Option<String>,
+    pub(crate) args: Vec<(String, String)>,
+    pub(crate) example: Option<String>,
+    /// Where the function is defined, like `lib/strings.nix:90`
+    pub(crate) location: Option<String>,
+}
+
+impl Filterable for XmlFuncDocumentation {
+    fn filter_field(&self, field: &str) -> Option<Cow<'_, str>> {
+        match field {
+            "type" => self.fn_type.as_deref().map(Cow::from),
+            "source" => Some(Cow::from(SOURCE_NIXPKGS_DOCUMENTATION)),
+            _ => None,
+        }
+    }
 }
 
 impl XmlFuncDocumentation {
@@ -23,42 +45,74 @@ impl XmlFuncDocumentation {
         self.name.to_string()
     }
 
-    pub fn pretty_printed(&self) -> String {
+    /// File defining the function, relative to nixpkgs
+    pub fn defining_file(&self) -> Option<PathBuf> {
+        let location = self.location.as_ref()?;
+        let file = location
+            .rsplit_once(':')
+            .map_or(location.as_str(), |(file, _)| file);
+        Some(PathBuf::from(file))
+    }
+
+    pub fn pretty_printed(&self, highlighter: &Highlighter) -> String {
         let mut output = String::new();
         if let Some(function_type) = &self.fn_type {
             output.push_str(&format!(
                 "# {} ({})\n",
-                self.name.blue().bold(),
+                highlighter.name(&self.name, |s| s.blue().bold()),
                 function_type.cyan()
             ));
         } else {
-            output.push_str(&format!("# {}\n", self.name.blue()));
+            output.push_str(&format!(
+                "# {}\n",
+                highlighter.name(&self.name, |s| s.blue())
+            ));
         }
-        output.push_str(&format!("{}\n", self.description));
+        output.push_str(&format!(
+            "{}\n",
+            highlighter.text(&self.description, |s| s.normal())
+        ));
         if !self.args.is_empty() {
             output.push_str("\nArguments:\n");
             for (name, description) in &self.args {
-                output.push_str(&format!("  {}: {}\n", name.green(), description));
+                output.push_str(&format!(
+                    "  {}: {}\n",
+                    highlighter.text(name, |s| s.green()),
+                    highlighter.text(description, |s| s.normal())
+                ));
             }
         }
         if let Some(example) = &self.example {
             output.push_str("\nExample:\n");
             for line in example.lines() {
-                output.push_str(&format!("  {}\n", line.white()));
+                output.push_str(&format!("  {}\n", highlighter.text(line, |s| s.white())));
             }
         }
         output
     }
 
+    /// Fields searched in full-text mode, besides the name
+    pub fn text_fields(&self) -> Vec<(&'static str, &str)> {
+        let mut fields = vec![("description", self.description.as_str())];
+        for (name, description) in &self.args {
+            fields.push(("args", name));
+            fields.push(("args", description));
+        }
+        if let Some(example) = &self.example {
+            fields.push(("example", example));
+        }
+        fields
+    }
+
     fn from_function_section_node(node: &roxmltree::Node) -> Option<Self> {
         let name = node.first_element_child()?.first_element_child()?.text()?;
         let desc = node.descendants().find(|x| is_tag(x, "para"))?.text()?;
         let fn_type = node
             .descendants()
             .find(|n| {
-                is_tag(&n, "subtitle")
+                is_tag(n, "subtitle")
                     && n.first_element_child()
-                        .map_or(false, |n| is_tag(&n, "literal"))
+                        .is_some_and(|n| is_tag(&n, "literal"))
             })
             .and_then(|n| n.first_element_child())
             .and_then(|n| n.text())
@@ -66,13 +120,13 @@ impl XmlFuncDocumentation {
 
         let args: Vec<_> = node
             .descendants()
-            .find(|n| is_tag(&n, "variablelist"))
+            .find(|n| is_tag(n, "variablelist"))
             .map(|list| {
                 list.children()
                     .filter(|n| n.is_element())
                     .filter_map(|entry| {
-                        let name = entry.descendants().find(|n| is_tag(&n, "varname"));
-                        let desc = entry.descendants().find(|n| is_tag(&n, "para"));
+                        let name = entry.descendants().find(|n| is_tag(n, "varname"));
+                        let desc = entry.descendants().find(|n| is_tag(n, "para"));
                         if let (Some(name), Some(desc)) =
                             (name.and_then(|x| x.text()), desc.and_then(|x| x.text()))
                         {
@@ -87,8 +141,8 @@ impl XmlFuncDocumentation {
 
         let example = node
             .descendants()
-            .find(|n| is_tag(&n, "example"))
-            .and_then(|n| n.descendants().find(|n| is_tag(&n, "programlisting")))
+            .find(|n| is_tag(n, "example"))
+            .and_then(|n| n.descendants().find(|n| is_tag(n, "programlisting")))
             .map(|n| {
                 n.descendants()
                     .filter_map(|n| n.text())
@@ -102,46 +156,63 @@ impl XmlFuncDocumentation {
             fn_type,
             example,
             args,
+            location: None,
         })
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct XmlFuncDocDatabase {
-    pub functions: HashMap<String, XmlFuncDocumentation>,
+/// Function locations, from sections like
+/// `<section><title>lib.strings.concatStrings</title><para>Located at <link>lib/strings.nix:49</link> ...`
+fn locations_in<'a>(document: &'a Document) -> impl Iterator<Item = (String, String)> + 'a {
+    document
+        .descendants()
+        .filter(|x| is_tag(x, "section"))
+        .filter_map(|section| {
+            let title = section
+                .first_element_child()
+                .filter(|c| is_tag(c, "title"))?;
+            let para = section.children().find(|n| {
+                is_tag(n, "para") && n.text().is_some_and(|t| t.trim().starts_with("Located at"))
+            })?;
+            let link = para.children().find(|n| is_tag(n, "link"))?;
+            Some((
+                title.text()?.trim().to_owned(),
+                link.text()?.trim().to_owned(),
+            ))
+        })
 }
 
-impl XmlFuncDocDatabase {
-    pub fn new() -> Self {
-        Self {
-            functions: HashMap::new(),
+/// Reads the documentation of nixpkgs' functions, for a `FuncDocDatabase`
+pub trait FuncDocLoader {
+    /// Generates the documentation and reads the functions it documents
+    fn load() -> Result<Vec<XmlFuncDocumentation>, Errors>;
+}
+
+/// Loads the functions of both loaders, so either kind of nixpkgs is documented. A function
+/// documented by both only gets `A`'s documentation; it's an error only if both fail.
+impl<A: FuncDocLoader, B: FuncDocLoader> FuncDocLoader for (A, B) {
+    fn load() -> Result<Vec<XmlFuncDocumentation>, Errors> {
+        match (A::load(), B::load()) {
+            (Err(e), Err(_)) => Err(e),
+            // later functions replace earlier ones with the same name
+            (a, b) => Ok(b
+                .unwrap_or_default()
+                .into_iter()
+                .chain(a.unwrap_or_default())
+                .collect()),
         }
     }
 }
 
-impl Cache for XmlFuncDocDatabase {}
+/// The DocBook documentation of older nixpkgs
+#[derive(Debug, Clone, Copy)]
+pub struct DocBook;
 
-impl DocSource for XmlFuncDocDatabase {
-    fn all_keys(&self) -> Vec<&str> {
-        self.functions.keys().map(|x| x.as_str()).collect()
-    }
-    fn search(&self, query: &Lowercase) -> Vec<crate::DocEntry> {
-        self.functions
-            .iter()
-            .filter(|(key, _)| starts_with_insensitive_ascii(key.as_bytes(), query))
-            .map(|(_, value)| DocEntry::XmlFuncDoc(value.clone()))
-            .collect()
-    }
-    fn search_liberal(&self, query: &Lowercase) -> Vec<DocEntry> {
-        self.functions
-            .iter()
-            .filter(|(key, _)| contains_insensitive_ascii(key.as_bytes(), query))
-            .map(|(_, value)| DocEntry::XmlFuncDoc(value.clone()))
-            .collect()
-    }
-    fn update(&mut self) -> Result<bool, Errors> {
-        let doc_path = &generate_docs();
+impl FuncDocLoader for DocBook {
+    fn load() -> Result<Vec<XmlFuncDocumentation>, Errors> {
+        let doc_path = &generate_docs()?;
         let mut result = Vec::new();
+        let mut locations = HashMap::new();
         for file in xml_files_in(doc_path) {
             let content = std::fs::read_to_string(&file).map_err(|e| Errors::FileIo {
                 err: e,
@@ -154,23 +225,176 @@ impl DocSource for XmlFuncDocDatabase {
 
             let mut function_entries = document
                 .descendants()
-                .filter(|x| is_tag(&x, "section"))
+                .filter(|x| is_tag(x, "section"))
                 .filter(|x| {
-                    x.first_element_child().map_or(false, |c| {
+                    x.first_element_child().is_some_and(|c| {
                         is_tag(&c, "title")
                             && c.first_element_child()
-                                .map_or(false, |f| is_tag(&f, "function"))
+                                .is_some_and(|f| is_tag(&f, "function"))
                     })
                 })
                 .filter_map(|node| XmlFuncDocumentation::from_function_section_node(&node))
                 .collect::<Vec<_>>();
             result.append(&mut function_entries);
+            locations.extend(locations_in(&document));
         }
 
-        let new = result.into_iter().map(|x| (x.name(), x)).collect();
+        Ok(result
+            .into_iter()
+            .map(|mut x| {
+                x.location = locations.get(&x.name).cloned();
+                x
+            })
+            .collect())
+    }
+}
+
+/// Documentation of nixpkgs' functions, read by the loader `L`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FuncDocDatabase<L> {
+    pub functions: BTreeMap<String, XmlFuncDocumentation>,
+    index: SearchIndex<String>,
+    #[serde(skip)]
+    loader: PhantomData<L>,
+}
+
+pub type XmlFuncDocDatabase = FuncDocDatabase<DocBook>;
+
+impl<L> Default for FuncDocDatabase<L> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L> FuncDocDatabase<L> {
+    pub fn new() -> Self {
+        Self {
+            functions: BTreeMap::new(),
+            index: SearchIndex::default(),
+            loader: PhantomData,
+        }
+    }
+
+    /// Replaces the functions, returns whether their names changed
+    pub(crate) fn replace_functions(
+        &mut self,
+        functions: impl IntoIterator<Item = XmlFuncDocumentation>,
+    ) -> bool {
+        let new = functions.into_iter().map(|x| (x.name(), x)).collect();
         let old = std::mem::replace(&mut self.functions, new);
+        self.build_index();
+
+        !self.functions.keys().eq(old.keys())
+    }
+
+    fn build_index(&mut self) {
+        self.index = self
+            .functions
+            .iter()
+            .map(|(key, value)| (key.clone(), key.as_str(), value.text_fields()))
+            .collect();
+    }
+
+    /// Functions with the given names, or all of them if the index couldn't narrow them down
+    fn candidates(&self, keys: Option<Vec<&String>>) -> Vec<(&String, &XmlFuncDocumentation)> {
+        match keys {
+            Some(keys) => keys
+                .into_iter()
+                .filter_map(|k| self.functions.get_key_value(k))
+                .collect(),
+            None => self.functions.iter().collect(),
+        }
+    }
 
-        Ok(!self.functions.keys().eq(old.keys()))
+    fn search_with(
+        &self,
+        limit: Option<usize>,
+        keys: Option<Vec<&String>>,
+        score: impl Fn(&[u8], &XmlFuncDocumentation) -> Option<Match>,
+    ) -> Vec<SearchResult> {
+        let matches = self
+            .candidates(keys)
+            .into_iter()
+            .filter_map(|(key, value)| Some((score(key.as_bytes(), value)?, value)))
+            .collect();
+        top_results(matches, limit, |m, value| {
+            SearchResult::new(DocEntry::XmlFuncDoc(value.clone()), m.score)
+                .with_name_ranges(m.ranges)
+        })
+    }
+}
+
+impl<L: FuncDocLoader> Cache for FuncDocDatabase<L> {}
+
+impl<L: FuncDocLoader> DocSource for FuncDocDatabase<L> {
+    fn sources(&self) -> Vec<&str> {
+        vec![SOURCE_NIXPKGS_DOCUMENTATION]
+    }
+    fn all_keys(&self) -> Vec<&str> {
+        self.functions.keys().map(|x| x.as_str()).collect()
+    }
+    fn search(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, self.index.prefix_candidates(query), |key, d| {
+            query.score(key, d, prefix_score_insensitive_ascii)
+        })
+    }
+    fn search_liberal(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, self.index.key_candidates(query), |key, d| {
+            query.score(key, d, match_score_insensitive_ascii)
+        })
+    }
+    fn search_fuzzy(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |key, d| {
+            query.score(key, d, fuzzy_score_insensitive_ascii)
+        })
+    }
+    fn search_abbreviation(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |key, d| {
+            query.score(key, d, abbreviation_score_insensitive_ascii)
+        })
+    }
+    fn search_regex(&self, regex: &Regex, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |key, _| regex_score(key, regex))
+    }
+    fn search_glob(&self, glob: &Glob, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(
+            limit,
+            self.index.literal_candidates(glob.longest_literal()),
+            |key, _| glob.score_dotted(key),
+        )
+    }
+    fn lookup(&self, name: &str) -> Vec<SearchResult> {
+        self.functions
+            .get(name)
+            .map(|d| SearchResult::exact(DocEntry::XmlFuncDoc(d.clone())))
+            .into_iter()
+            .collect()
+    }
+    fn search_type(&self, signature: &Signature, limit: Option<usize>) -> Vec<SearchResult> {
+        self.search_with(limit, None, |_, d| {
+            let fn_type = Signature::parse(d.fn_type.as_ref()?)?;
+            Some(Match::new(signature.score(&fn_type)?, Vec::new()))
+        })
+    }
+    fn search_full_text(&self, query: &Query, limit: Option<usize>) -> Vec<SearchResult> {
+        let matches = self
+            .candidates(self.index.full_text_candidates(query))
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let (m, fields) =
+                    full_text_match(key.as_bytes(), value, &value.text_fields(), query)?;
+                Some((m, (value, fields)))
+            })
+            .collect();
+        top_results(matches, limit, |m, (value, fields)| {
+            let entry = DocEntry::XmlFuncDoc(value.clone());
+            SearchResult::new(entry, m.score)
+                .with_matched_fields(fields)
+                .with_name_ranges(m.ranges)
+        })
+    }
+    fn update(&mut self) -> Result<bool, Errors> {
+        Ok(self.replace_functions(L::load()?))
     }
 }
 
@@ -188,13 +412,84 @@ fn xml_files_in(path: &PathBuf) -> Vec<PathBuf> {
         .collect::<Vec<PathBuf>>()
 }
 
-fn generate_docs() -> PathBuf {
-    let doc_path = Command::new("nix-build")
+fn generate_docs() -> Result<PathBuf, Errors> {
+    let output = Command::new("nix-build")
         .arg("--no-out-link")
         .arg("<nixpkgs/doc/doc-support/default.nix>")
-        .output()
-        .ok()
-        .and_then(|o| String::from_utf8(o.stdout).ok())
-        .unwrap();
-    PathBuf::from(doc_path.trim_end_matches("\n")).join("function-docs")
+        .output()?;
+    // current nixpkgs no longer has it, its functions are documented in markdown
+    if !output.status.success() {
+        return Err(Errors::DocBuild(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    let doc_path = String::from_utf8_lossy(&output.stdout);
+    Ok(PathBuf::from(doc_path.trim_end_matches('\n')).join("function-docs"))
+}
+
+#[test]
+fn test_locations_in() {
+    let document = Document::parse(
+        r#"<chapter xmlns:xlink="http://www.w3.org/1999/xlink">
+          <section><title>lib.strings.concatStrings</title>
+            <para xml:id="lib.strings.concatStrings">
+            Located at
+            <link xlink:href="https://github.com/NixOS/nixpkgs/blob/master/lib/strings.nix#L49">lib/strings.nix:49</link> in <literal>&lt;nixpkgs&gt;</literal>.
+            </para>
+          </section>
+          <section><title>Not a location</title><para>Something else</para></section>
+        </chapter>"#,
+    )
+    .unwrap();
+    assert_eq!(
+        locations_in(&document).collect::<Vec<_>>(),
+        vec![(
+            "lib.strings.concatStrings".to_owned(),
+            "lib/strings.nix:49".to_owned()
+        )]
+    );
+}
+
+#[test]
+fn test_loader_pair() {
+    fn function(description: &str) -> XmlFuncDocumentation {
+        XmlFuncDocumentation {
+            name: "lib.id".to_owned(),
+            description: description.to_owned(),
+            fn_type: None,
+            args: Vec::new(),
+            example: None,
+            location: None,
+        }
+    }
+    struct Markdown;
+    impl FuncDocLoader for Markdown {
+        fn load() -> Result<Vec<XmlFuncDocumentation>, Errors> {
+            Ok(vec![function("From markdown")])
+        }
+    }
+    struct Xml;
+    impl FuncDocLoader for Xml {
+        fn load() -> Result<Vec<XmlFuncDocumentation>, Errors> {
+            Ok(vec![function("From DocBook")])
+        }
+    }
+    struct Failing;
+    impl FuncDocLoader for Failing {
+        fn load() -> Result<Vec<XmlFuncDocumentation>, Errors> {
+            Err(Errors::DocBuild("no documentation".to_owned()))
+        }
+    }
+
+    let mut database = FuncDocDatabase::<(Markdown, Xml)>::new();
+    assert!(database.update().unwrap());
+    assert_eq!(database.all_keys(), vec!["lib.id"]);
+    assert_eq!(database.functions["lib.id"].description, "From markdown");
+
+    let mut database = FuncDocDatabase::<(Failing, Xml)>::new();
+    assert!(database.update().unwrap());
+    assert_eq!(database.functions["lib.id"].description, "From DocBook");
+    assert!(FuncDocDatabase::<(Failing, Failing)>::new()
+        .update()
+        .is_err());
 }
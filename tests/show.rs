@@ -0,0 +1,32 @@
+use std::{
+    path::Path,
+    process::{Command, Output},
+};
+
+/// Runs `manix show NAME` on the nixdoc fixtures, with its caches in a directory of its own
+fn show(name: &str) -> Output {
+    let cache = Path::new(env!("CARGO_TARGET_TMPDIR")).join("show-cache");
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/nixdoc");
+    Command::new(env!("CARGO_BIN_EXE_manix"))
+        .env("XDG_CACHE_HOME", &cache)
+        .arg("--source=nixpkgs_doc")
+        .arg("--nixdoc-dir")
+        .arg(&fixtures)
+        .args(["show", name])
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_show() {
+    let output = show("lib.strings.concatStringsSep");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("lib.strings.concatStringsSep"));
+    assert!(!stdout.contains("lib.strings.concatStrings "));
+
+    let output = show("lib.strings.concatStringsS");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No entry named"));
+}